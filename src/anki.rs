@@ -1,42 +1,359 @@
 use rusqlite::Connection;
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
-use tracing::{debug,info};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tracing::{debug,warn};
+use serde::{Deserialize, Serialize};
+use anyhow::anyhow;
+use crate::config::{AnkiNote, FieldMap};
+use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 type Dupa<T> = Result<T, Box<dyn std::error::Error>>;
 
+/// A note from the collection with the fields picked by `FieldMap`
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub id: i64,
+    pub created: DateTime<Utc>,
+    pub word: String,
+    pub reading: Option<String>,
+    pub meaning: Option<String>,
+    pub review: Option<Review>,
+}
+
+/// Scheduling state of a note, taken from its least advanced card
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Review {
+    /// Days, 0 for new and learning cards
+    pub interval: i64,
+    /// 2.5 means 250%
+    pub ease: f32,
+    pub due: Option<DateTime<Utc>>,
+    pub lapses: i64,
+    pub reps: i64,
+    pub suspended: bool,
+    pub new: bool,
+    pub last_review: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for Review {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.new {
+            write!(f, "New")?;
+        } else {
+            write!(f, "Interval {} d · Ease {:.0}% · Lapses {}", self.interval, self.ease * 100.0, self.lapses)?;
+            if let Some(d) = self.due {
+                write!(f, " · Due {}", d.format("%Y-%m-%d"))?;
+            }
+            if let Some(d) = self.last_review {
+                write!(f, " · Reviewed {}", d.format("%Y-%m-%d"))?;
+            }
+        }
+        if self.suspended {
+            write!(f, " · Suspended")?;
+        }
+        Ok(())
+    }
+}
+
+/// How well a word is known according to the collection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Knowledge {
+    Known,
+    Learning,
+    Unknown,
+}
+
+impl Note {
+    fn matches(&self, key: &str) -> bool {
+        self.word.contains(key)
+            || self.reading.as_ref().is_some_and(|r| r.contains(key))
+            || self.meaning.as_ref().is_some_and(|m| m.contains(key))
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.word)?;
+        if let Some(r) = &self.reading {
+            write!(f, " [{}]", r)?;
+        }
+        if let Some(m) = &self.meaning {
+            write!(f, " {}", m)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Anki {
-    AnkiDb { data: HashMap<String, DateTime<Utc>> },
+    AnkiDb { data: HashMap<String, Note> },
     None
 }
 
 impl Anki {
     pub const PATTERN: &'static str = "<.*?>";
-    pub const FLDS_PATTERN: &'static str = r"^(<div>)?(?P<key>[^<]+)(<\/div>)?\u{001F}(.+)<(div|br)>(?P<val>[^<\n]+)(<\/div>)?";
     pub const NO_ANKI: &'static str = "No Anki database found";
-    pub fn new(fname: &str) -> Self {
-        if let Ok(c) = Connection::open_with_flags(fname, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY) {
-            let mut st = c.prepare("SELECT REPLACE(sfld, CHAR(10), ' '),id FROM notes").unwrap();
-            let mut e_it = st.query([]).unwrap();
-            let mut data = HashMap::new();
-            debug!("Loading Anki");
-            while let Ok(e) = e_it.next() {
-                if let Some(e) = e {
-                    let word: String = e.get_unwrap(0);
-                    let ts: i64 = e.get_unwrap(1);
-                    let date = DateTime::from_timestamp_millis(ts).unwrap();
-                    data.insert(word, date);
-                } else {
-                    break;
+    /// Field map used for note types missing in the configuration
+    pub const DEFAULT_FIELDS: &'static str = "default";
+    /// Interval in days from which a word counts as known
+    pub const KNOWN_INTERVAL: i64 = 21;
+
+    /// Note type id -> name, empty for collections older than the notetypes table
+    fn note_types(c: &Connection) -> HashMap<i64, String> {
+        let mut res = HashMap::new();
+        if let Ok(mut st) = c.prepare("SELECT id, name FROM notetypes") {
+            if let Ok(mut rows) = st.query([]) {
+                while let Ok(Some(r)) = rows.next() {
+                    res.insert(r.get_unwrap(0), r.get_unwrap(1));
                 }
             }
-            Self::AnkiDb { data }
+        }
+        res
+    }
+
+    /// Deck id -> full name with `::` between the levels
+    fn deck_names(c: &Connection) -> HashMap<i64, String> {
+        let mut res = HashMap::new();
+        if let Ok(mut st) = c.prepare("SELECT id, name FROM decks") {
+            if let Ok(mut rows) = st.query([]) {
+                while let Ok(Some(r)) = rows.next() {
+                    res.insert(r.get_unwrap(0), r.get_unwrap::<_, String>(1).replace('\u{1F}', "::"));
+                }
+            }
+        } else if let Ok(json) = c.query_row("SELECT decks FROM col", [], |r| r.get::<_, String>(0)) {
+            // Older collections keep decks as JSON in col
+            if let Ok(serde_json::Value::Object(decks)) = serde_json::from_str(&json) {
+                for d in decks.values() {
+                    if let (Some(id), Some(name)) = (d["id"].as_i64(), d["name"].as_str()) {
+                        res.insert(id, name.to_string());
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Ids of the decks named in `decks` and of their subdecks, with the names not found
+    fn deck_ids(c: &Connection, decks: &[String]) -> (HashSet<i64>, Vec<String>) {
+        let names: Vec<(i64, String)> = Self::deck_names(c).into_iter()
+            .map(|(id, n)| (id, n.to_lowercase()))
+            .collect();
+        let mut res = HashSet::new();
+        let mut missing = vec![];
+        for deck in decks {
+            let d = deck.to_lowercase();
+            let found: Vec<i64> = names.iter()
+                .filter(|(_, n)| *n == d || n.starts_with(&format!("{}::", d)))
+                .map(|(id, _)| *id)
+                .collect();
+            if found.is_empty() {
+                warn!("No deck named {}, skipped", deck);
+                missing.push(deck.clone());
+            }
+            res.extend(found);
+        }
+        (res, missing)
+    }
+
+    /// Review state of every note, cards are joined with their last entry in revlog.
+    /// With `dids` only cards in these decks are counted.
+    fn reviews(c: &Connection, dids: Option<&HashSet<i64>>) -> HashMap<i64, Review> {
+        let mut res: HashMap<i64, Review> = HashMap::new();
+        let crt: i64 = c.query_row("SELECT crt FROM col", [], |r| r.get(0)).unwrap_or(0);
+        let Ok(mut st) = c.prepare(
+            "SELECT c.nid, c.type, c.queue, c.due, c.ivl, c.factor, c.reps, c.lapses, r.last, c.did, c.odid
+             FROM cards c LEFT JOIN (SELECT cid, MAX(id) AS last FROM revlog GROUP BY cid) r ON r.cid = c.id") else {
+            return res;
+        };
+        let Ok(mut rows) = st.query([]) else {
+            return res;
+        };
+        while let Ok(Some(r)) = rows.next() {
+            // Cards in a filtered deck belong to the original one
+            let did: i64 = r.get_unwrap(9);
+            let odid: i64 = r.get_unwrap(10);
+            if dids.is_some_and(|d| !d.contains(&did) && !d.contains(&odid)) {
+                continue;
+            }
+            let nid: i64 = r.get_unwrap(0);
+            let ctype: i64 = r.get_unwrap(1);
+            let queue: i64 = r.get_unwrap(2);
+            let due: i64 = r.get_unwrap(3);
+            let last: Option<i64> = r.get_unwrap(8);
+            let new = ctype == 0;
+            // Learning cards are due at a timestamp, review cards on a day counted from the collection creation
+            let due = match new {
+                true => None,
+                false if due > 1_000_000_000 => DateTime::from_timestamp(due, 0),
+                false => DateTime::from_timestamp(crt + due * 86400, 0),
+            };
+            let card = Review {
+                interval: r.get_unwrap::<_, i64>(4).max(0),
+                ease: r.get_unwrap::<_, i64>(5) as f32 / 1000.0,
+                due,
+                lapses: r.get_unwrap(7),
+                reps: r.get_unwrap(6),
+                suspended: queue == -1,
+                new,
+                last_review: last.and_then(DateTime::from_timestamp_millis),
+            };
+            match res.get_mut(&nid) {
+                Some(n) => {
+                    let suspended = n.suspended && card.suspended;
+                    if (card.new, -card.interval) > (n.new, -n.interval) {
+                        *n = card;
+                    }
+                    n.suspended = suspended;
+                }
+                None => {
+                    res.insert(nid, card);
+                }
+            }
+        }
+        res
+    }
+
+    /// A copy of the collection, a running Anki keeps the original locked
+    fn snapshot(fname: &str) -> anyhow::Result<PathBuf> {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_nanos();
+        let dst = std::env::temp_dir().join(format!("cnreader-anki-{}-{}.anki2", std::process::id(), nanos));
+        fs::copy(fname, &dst)?;
+        let wal = format!("{}-wal", fname);
+        if Path::new(&wal).exists() {
+            fs::copy(&wal, format!("{}-wal", dst.display()))?;
+        }
+        Ok(dst)
+    }
+
+    /// Reads the collection at `fname`, the error says why it couldn't be used.
+    /// With `decks` only notes with cards in them are loaded, the names it doesn't have are returned.
+    pub fn load(fname: &str, fields: &BTreeMap<String, FieldMap>, decks: &[String]) -> anyhow::Result<(Self, Vec<String>)> {
+        if !Path::new(fname).exists() {
+            return Err(anyhow!("No Anki collection at {}", fname));
+        }
+        let copy = Self::snapshot(fname)?;
+        let res = Connection::open(&copy)
+            .map_err(anyhow::Error::from)
+            .and_then(|c| Self::read(&c, fields, decks));
+        for ext in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", copy.display(), ext));
+        }
+        res
+    }
+
+    /// Notes of all collections, the first one wins for words found in several.
+    /// Errors are the reasons why some of them couldn't be read and the decks none of them has.
+    pub fn load_all(fnames: &[String], fields: &BTreeMap<String, FieldMap>, decks: &[String]) -> (Self, Vec<String>) {
+        let mut all: Option<HashMap<String, Note>> = None;
+        let mut errors = vec![];
+        let mut missing: Vec<Vec<String>> = vec![];
+        for f in fnames {
+            match Self::load(f, fields, decks) {
+                Ok((anki, m)) => {
+                    missing.push(m);
+                    if let Self::AnkiDb { data } = anki {
+                        let all = all.get_or_insert_with(HashMap::new);
+                        for (k, v) in data {
+                            all.entry(k).or_insert(v);
+                        }
+                    }
+                }
+                Err(e) => errors.push(if fnames.len() > 1 { format!("{}: {}", f, e) } else { e.to_string() }),
+            }
+        }
+        errors.extend(decks.iter()
+            .filter(|d| !missing.is_empty() && missing.iter().all(|m| m.contains(d)))
+            .map(|d| format!("No deck named {}", d)));
+        (all.map_or(Self::None, |data| Self::AnkiDb { data }), errors)
+    }
+
+    fn read(c: &Connection, fields: &BTreeMap<String, FieldMap>, decks: &[String]) -> anyhow::Result<(Self, Vec<String>)> {
+        let types = Self::note_types(c);
+        let (dids, missing) = if decks.is_empty() {
+            (None, vec![])
         } else {
-            info!("Anki disabled");
-            Self::None
+            let (ids, missing) = Self::deck_ids(c, decks);
+            (Some(ids), missing)
+        };
+        let mut reviews = Self::reviews(c, dids.as_ref());
+        let html = Regex::new(Self::PATTERN).unwrap();
+        let clean = |s: &str| -> String {
+            let s = s.replace("<br>", " ").replace("<div>", " ").replace("&nbsp;", " ");
+            html.replace_all(&s, "").split_whitespace().collect::<Vec<&str>>().join(" ")
+        };
+
+        let mut st = c.prepare("SELECT REPLACE(sfld, CHAR(10), ' '), id, mid, flds FROM notes")?;
+        let mut e_it = st.query([])?;
+        let mut data = HashMap::new();
+        debug!("Loading Anki");
+        while let Ok(e) = e_it.next() {
+            if let Some(e) = e {
+                let sfld: String = e.get_unwrap(0);
+                let id: i64 = e.get_unwrap(1);
+                let mid: i64 = e.get_unwrap(2);
+                if dids.is_some() && !reviews.contains_key(&id) {
+                    continue;
+                }
+                let flds: String = e.get_unwrap(3);
+                let flds: Vec<String> = flds.split('\u{1F}').map(clean).collect();
+                let map = types.get(&mid)
+                    .and_then(|n| fields.get(n))
+                    .or_else(|| fields.get(&mid.to_string()))
+                    .or_else(|| fields.get(Self::DEFAULT_FIELDS));
+                let get = |i: Option<usize>| i.and_then(|i| flds.get(i)).filter(|f| !f.is_empty()).cloned();
+                let Some(created) = DateTime::from_timestamp_millis(id) else {
+                    warn!("Note {} has an invalid id, skipped", id);
+                    continue;
+                };
+
+                let note = match map {
+                    Some(m) => Note {
+                        id,
+                        created,
+                        word: get(Some(m.word)).unwrap_or_default(),
+                        reading: get(m.reading),
+                        meaning: get(m.meaning),
+                        review: reviews.remove(&id),
+                    },
+                    // Unmapped note types: the sort field and the rest as meaning
+                    None => {
+                        let word = clean(&sfld);
+                        let rest = flds.iter()
+                            .filter(|f| !f.is_empty() && **f != word)
+                            .cloned()
+                            .collect::<Vec<String>>();
+                        Note {
+                            id,
+                            created,
+                            word,
+                            reading: None,
+                            meaning: (!rest.is_empty()).then(|| rest.join(" | ")),
+                            review: reviews.remove(&id),
+                        }
+                    }
+                };
+                if note.word.is_empty() {
+                    continue;
+                }
+                data.insert(note.word.clone(), note);
+            } else {
+                break;
+            }
         }
+        debug!("Anki notes: {}", data.len());
+        Ok((Self::AnkiDb { data }, missing))
+    }
 
+    /// Latest modification time of the collections and their write-ahead logs
+    pub fn modified(fnames: &[String]) -> Option<SystemTime> {
+        fnames.iter()
+            .flat_map(|f| [f.clone(), format!("{}-wal", f)])
+            .filter_map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .max()
     }
 
     pub fn is_none(&self) -> bool {
@@ -56,16 +373,176 @@ impl Anki {
         }
     }
 
-    pub fn search(&self, key: &str) -> Vec<&String> {
+    pub fn note(&self, key: &str) -> Option<&Note> {
+        match self {
+            Self::None => None,
+            Self::AnkiDb { data } => data.get(key),
+        }
+    }
+
+    /// Known words have a review card with an interval of at least `known_interval` days
+    pub fn knowledge(&self, key: &str, known_interval: i64) -> Knowledge {
+        match self.note(key).map(|n| n.review.as_ref()) {
+            None => Knowledge::Unknown,
+            Some(Some(r)) if !r.new && r.interval >= known_interval => Knowledge::Known,
+            Some(_) => Knowledge::Learning,
+        }
+    }
+
+    /// Notes with `key` in the word, reading or meaning
+    pub fn search(&self, key: &str) -> Vec<&Note> {
         match self {
             Self::None => vec![],
             Self::AnkiDb { data } => {
-                data.keys()
-                    .filter(|&k| k.contains(key))
+                data.values()
+                    .filter(|n| n.matches(key))
                     .collect()
             }
         }
     }
+}
+
+//------- AnkiConnect --------------
+
+/// Contents of a note created from a dictionary entry
+#[derive(Clone, Debug, Default)]
+pub struct NewNote {
+    pub word: String,
+    pub reading: String,
+    pub meaning: String,
+    pub sentence: Option<String>,
+    pub context: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectNote {
+    deck_name: String,
+    model_name: String,
+    fields: BTreeMap<String, String>,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ConnectRequest<P: Serialize> {
+    action: &'static str,
+    version: u32,
+    params: P,
+}
+
+#[derive(Deserialize)]
+struct ConnectResponse<T> {
+    result: Option<T>,
+    error: Option<String>,
+}
+
+pub const ANKI_CONNECT: &str = "http://127.0.0.1:8765";
+
+/// Create a note through the AnkiConnect compatible endpoint at `conf.url`, returns the note id
+pub async fn add_note(conf: &AnkiNote, note: NewNote) -> anyhow::Result<i64> {
+    let mut fields = BTreeMap::new();
+    fields.insert(conf.word_field.clone(), note.word);
+    let optional = [
+        (&conf.reading_field, Some(note.reading)),
+        (&conf.meaning_field, Some(note.meaning)),
+        (&conf.sentence_field, note.sentence),
+        (&conf.context_field, note.context),
+    ];
+    for (field, value) in optional {
+        if let (Some(f), Some(v)) = (field, value) {
+            fields.insert(f.clone(), v);
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Params {
+        note: ConnectNote,
+    }
+    let req = ConnectRequest {
+        action: "addNote",
+        version: 6,
+        params: Params {
+            note: ConnectNote {
+                deck_name: conf.deck.clone(),
+                model_name: conf.note_type.clone(),
+                fields,
+                tags: conf.tags.clone().unwrap_or_default(),
+            },
+        },
+    };
+
+    let url = conf.url.as_deref().unwrap_or(ANKI_CONNECT);
+    debug!("AnkiConnect addNote at {}", url);
+    let resp: ConnectResponse<i64> = reqwest::Client::new()
+        .post(url)
+        .json(&req)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    match (resp.result, resp.error) {
+        (_, Some(e)) => Err(anyhow!("AnkiConnect: {}", e)),
+        (Some(id), None) => Ok(id),
+        (None, None) => Err(anyhow!("AnkiConnect returned no note id")),
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
 
+    fn conf(url: String) -> AnkiNote {
+        AnkiNote {
+            url: Some(url),
+            deck: "Chinese".into(),
+            note_type: "Basic".into(),
+            tags: Some(vec!["cnreader".into()]),
+            word_field: "Front".into(),
+            meaning_field: Some("Back".into()),
+            sentence_field: Some("Sentence".into()),
+            ..Default::default()
+        }
+    }
+
+    fn note() -> NewNote {
+        NewNote {
+            word: "学习".into(),
+            reading: "xué xí".into(),
+            meaning: "to learn".into(),
+            sentence: Some("我们学习中文。".into()),
+            context: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn add_note_request() {
+        let (url, req) = mock::serve(200, vec![br#"{"result": 1496198395707, "error": null}"#.to_vec()]).await;
+        assert_eq!(add_note(&conf(url), note()).await.unwrap(), 1496198395707);
+
+        let req = req.await.unwrap().json();
+        assert_eq!(req["action"], "addNote");
+        assert_eq!(req["version"], 6);
+        let n = &req["params"]["note"];
+        assert_eq!(n["deckName"], "Chinese");
+        assert_eq!(n["modelName"], "Basic");
+        assert_eq!(n["tags"], serde_json::json!(["cnreader"]));
+        // Only the configured fields are sent
+        assert_eq!(n["fields"], serde_json::json!({"Front": "学习", "Back": "to learn", "Sentence": "我们学习中文。"}));
+    }
+
+    #[tokio::test]
+    async fn add_note_error() {
+        let reply = br#"{"result": null, "error": "cannot create note because it is a duplicate"}"#;
+        let (url, _) = mock::serve(200, vec![reply.to_vec()]).await;
+        let e = add_note(&conf(url), note()).await.unwrap_err();
+        assert_eq!(e.to_string(), "AnkiConnect: cannot create note because it is a duplicate");
+    }
+
+    #[tokio::test]
+    async fn add_note_http_error() {
+        let (url, _) = mock::serve(500, vec![b"oops".to_vec()]).await;
+        assert!(add_note(&conf(url), note()).await.is_err());
+    }
 }
@@ -0,0 +1,114 @@
+#[cfg(feature = "baseline")]
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::Instant;
+#[cfg(feature = "baseline")]
+use rayon::prelude::*;
+#[cfg(feature = "baseline")]
+use crate::cedict::Entry;
+use crate::cedict::Cedict;
+use crate::segment;
+use crate::Dupa;
+
+const SAMPLE: &str = "我们学习中文已经三年了。刚开始的时候，我觉得汉字非常难写，发音也不容易。\
+后来我每天看新闻、读小说，慢慢发现自己能看懂的东西越来越多。\
+现在我打算去中国旅行，希望能和当地人聊天，了解他们的生活和文化。";
+
+const SAMPLE_TRA: &str = "我們學習中文已經三年了。剛開始的時候，我覺得漢字非常難寫，發音也不容易。";
+
+/// Average time of `f` in microseconds
+fn time<T>(name: &str, n: u32, mut f: impl FnMut() -> T) {
+    let t = Instant::now();
+    for _ in 0..n {
+        black_box(f());
+    }
+    let us = t.elapsed().as_secs_f64() * 1e6 / n as f64;
+    println!("{:<32}{:>12.2} µs", name, us);
+}
+
+/// The dictionary before the indexes: entries grouped by their first character,
+/// anything else is a parallel scan over all groups
+#[cfg(feature = "baseline")]
+struct Baseline<'a> {
+    data_t: BTreeMap<char, Vec<&'a Entry>>,
+}
+
+#[cfg(feature = "baseline")]
+impl<'a> Baseline<'a> {
+    fn new(d: &'a Cedict) -> Self {
+        let mut data_t: BTreeMap<char, Vec<&Entry>> = BTreeMap::new();
+        for e in d.entries() {
+            data_t.entry(e.sim().chars().next().unwrap_or('?')).or_default().push(e);
+        }
+        Self { data_t }
+    }
+
+    fn scan(&self, f: impl Fn(&Entry) -> bool + Sync) -> Vec<&'a Entry> {
+        self.data_t.par_iter()
+            .map(|(_, v)| v.iter().filter(|e| f(e)).copied().collect())
+            .reduce(Vec::new, |a, b| [a, b].concat())
+    }
+
+    fn group(&self, s: &str, f: impl Fn(&Entry) -> bool) -> Vec<&'a Entry> {
+        s.chars().next()
+            .and_then(|c| self.data_t.get(&c))
+            .map(|v| v.iter().filter(|e| f(e)).copied().collect())
+            .unwrap_or_default()
+    }
+
+    fn find(&self, s: &str) -> Vec<&'a Entry> {
+        self.group(s, |e| e.sim() == s)
+    }
+
+    fn find_tra(&self, s: &str) -> Vec<&'a Entry> {
+        self.scan(|e| e.tra() == s)
+    }
+
+    fn search(&self, s: &str) -> Vec<&'a Entry> {
+        self.scan(|e| e.sim().contains(s))
+    }
+
+    fn prefix(&self, s: &str) -> Vec<&'a Entry> {
+        self.group(s, |e| e.sim().starts_with(s))
+    }
+
+    fn to_sim(&self, s: &str) -> String {
+        let chars = self.scan(|e| e.len() == 1 && (s.contains(e.sim()) || s.contains(e.tra())));
+        s.par_chars()
+            .map(|z| match chars.iter().find(|x| x.tra() == z.to_string()) {
+                Some(c) => c.sim().chars().next().unwrap_or(z),
+                None => z,
+            })
+            .collect()
+    }
+}
+
+/// Time the dictionary operations, with the `baseline` feature against the lookups they replaced
+pub fn run(db: &str) -> Dupa<()> {
+    let t = Instant::now();
+    let d = Cedict::new(db)?;
+    println!("Loaded {} entries in {:.2?}\n", d.len(), t.elapsed());
+    #[cfg(feature = "baseline")]
+    let old = Baseline::new(&d);
+
+    time("find 中国", 10000, || d.find("中国"));
+    #[cfg(feature = "baseline")]
+    time("  before", 10000, || old.find("中国"));
+    time("find_tra 頭髮", 10000, || d.find_tra("頭髮"));
+    #[cfg(feature = "baseline")]
+    time("  before", 100, || old.find_tra("頭髮"));
+    time("search 学", 1000, || d.search("学"));
+    #[cfg(feature = "baseline")]
+    time("  before", 100, || old.search("学"));
+    time("prefix 中国", 1000, || d.prefix("中国", Cedict::MAX_RESULTS));
+    #[cfg(feature = "baseline")]
+    time("  before", 1000, || old.prefix("中国"));
+    time("search_pinyin zhongguo", 1000, || d.search_pinyin("zhongguo"));
+    time("search_english study", 1000, || d.search_english("study"));
+    time("to_sim", 1000, || d.to_sim(SAMPLE_TRA));
+    #[cfg(feature = "baseline")]
+    time("  before", 100, || old.to_sim(SAMPLE_TRA));
+    time("to_tra", 1000, || d.to_tra(SAMPLE));
+    time("segment", 1000, || segment::segment(&d, SAMPLE));
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use iced::widget::{button, column, container, row, text, Column, Row};
+use iced::Element;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use crate::cedict::{Entry, Form};
+use crate::{anki, helper, pinyin, Message};
+
+/// Cross-reference in a CEDICT sense: `頭髮|头发[tou2 fa5]` or `乾[gan1]`
+static REF: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?P<a>[\p{Han}〇·]+)(\|(?P<b>[\p{Han}〇·]+))?\[(?P<pin>[^\]]+)\]").unwrap()
+});
+
+#[derive(Clone, Debug)]
+pub struct Sense {
+    pub text: String,
+    /// Simplified headwords mentioned in the sense
+    pub refs: Vec<String>,
+}
+
+impl Sense {
+    fn new(s: &str) -> Self {
+        let refs = REF.captures_iter(s)
+            .map(|c| c.name("b").unwrap_or_else(|| c.name("a").unwrap()).as_str().to_string())
+            .collect();
+        let text = REF.replace_all(s, |c: &regex::Captures| {
+            let w = c.name("b").unwrap_or_else(|| c.name("a").unwrap()).as_str();
+            format!("{} [{}]", w, pinyin::render(&c["pin"], pinyin::style()))
+        }).to_string();
+        Self { text, refs }
+    }
+
+    /// Clicking a sense looks up the word it refers to, or other words with the same meaning
+    fn query(&self) -> String {
+        self.refs.first().cloned().unwrap_or_else(|| self.text.clone())
+    }
+}
+
+/// A dictionary entry prepared for display
+#[derive(Clone, Debug)]
+pub struct Card {
+    pub headword: String,
+    /// Traditional form when it differs
+    pub traditional: Option<String>,
+    pub form: Form,
+    pub reading: String,
+    pub senses: Vec<Sense>,
+    pub hsk: Option<u32>,
+    pub in_anki: bool,
+    pub review: Option<anki::Review>,
+}
+
+impl Card {
+    /// Note fields for "Add to Anki"
+    pub fn to_note(&self, context: Option<&helper::Context>) -> anki::NewNote {
+        anki::NewNote {
+            word: self.headword.clone(),
+            reading: self.reading.clone(),
+            meaning: self.senses.iter().map(|s| s.text.as_str()).collect::<Vec<&str>>().join("; "),
+            sentence: context.map(|c| c.sentence.clone()),
+            context: context.map(|c| c.paragraph.clone()),
+        }
+    }
+
+    pub fn new(e: &Entry, form: Form, anki: &anki::Anki) -> Self {
+        Self {
+            headword: e.sim().to_string(),
+            traditional: (e.tra() != e.sim()).then(|| e.tra().to_string()),
+            form,
+            reading: pinyin::render(e.pin(), pinyin::style()),
+            senses: e.mea().split('/').filter(|s| !s.is_empty()).map(Sense::new).collect(),
+            hsk: e.hsk(),
+            in_anki: anki.contains(e.sim()) || anki.contains(e.tra()),
+            review: anki.note(e.sim()).or_else(|| anki.note(e.tra())).and_then(|n| n.review.clone()),
+        }
+    }
+}
+
+fn headword<'a>(w: &'a str, matched: bool, size: f32) -> Element<'a, Message> {
+    if matched {
+        text(w).size(size * 1.4).style(text::primary).into()
+    } else {
+        text(w).size(size * 1.4).into()
+    }
+}
+
+fn badge<'a>(label: String, size: f32) -> Element<'a, Message> {
+    container(text(label).size(size * 0.8))
+        .padding([1, 6])
+        .style(container::rounded_box)
+        .into()
+}
+
+fn card<'a>(i: usize, c: &'a Card, can_add: bool, size: f32) -> Element<'a, Message> {
+    let mut head = Row::new()
+        .spacing(10)
+        .align_y(iced::Alignment::Center)
+        .push(headword(&c.headword, c.form == Form::Simplified, size));
+    if let Some(t) = &c.traditional {
+        head = head.push(headword(t, c.form == Form::Traditional, size));
+    }
+    head = head.push(text(&c.reading).size(size));
+    if let Some(h) = c.hsk {
+        head = head.push(badge(format!("HSK {}", h), size));
+    }
+    if c.in_anki {
+        head = head.push(badge("Anki".to_string(), size));
+    } else if can_add {
+        head = head.push(button(text("+ Anki").size(size * 0.8))
+            .padding([1, 6])
+            .on_press(Message::AddToAnki(i)));
+    }
+
+    let senses = c.senses.iter().enumerate().fold(Column::new().spacing(2), |col, (i, s)| {
+        let mut r = Row::new()
+            .spacing(5)
+            .align_y(iced::Alignment::Center)
+            .push(text(format!("{}.", i + 1)).size(size))
+            .push(button(text(&s.text).size(size))
+                .padding(0)
+                .style(button::text)
+                .on_press(Message::Lookup(s.query())));
+        for w in &s.refs {
+            r = r.push(button(text(w).size(size))
+                .padding([0, 4])
+                .style(button::secondary)
+                .on_press(Message::Lookup(w.clone())));
+        }
+        col.push(r)
+    });
+
+    let mut body = column![head].spacing(5);
+    if let Some(r) = &c.review {
+        body = body.push(text(r.to_string()).size(size * 0.8));
+    }
+
+    container(body.push(senses))
+        .padding(8)
+        .width(iced::Length::Fill)
+        .style(container::bordered_box)
+        .into()
+}
+
+/// Lookup results as a list of cards, `info` is shown below them.
+/// With `can_add` cards not yet in Anki get an "Add to Anki" button.
+pub fn view<'a>(cards: &'a [Card], info: Option<&'a str>, can_add: bool, size: f32) -> Element<'a, Message> {
+    let mut col = cards.iter()
+        .enumerate()
+        .fold(Column::new().spacing(6), |col, (i, c)| col.push(card(i, c, can_add, size)));
+    if let Some(info) = info {
+        col = col.push(text(info).size(size));
+    }
+    row![col].padding([0, 10]).into()
+}
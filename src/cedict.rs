@@ -1,9 +1,10 @@
 use rusqlite::{Connection, Row};
 use std::fmt;
 use crate::Dupa;
-use rayon::prelude::*;
+use crate::pinyin;
+use crate::convert::Converter;
 use tracing::debug;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Clone, Debug)]
 pub struct Entry {
@@ -13,14 +14,12 @@ pub struct Entry {
     mea: String,
     hsk: Option<u32>,
     chr: bool,
-    idx: char,
 }
 
 impl Entry {
     pub fn from_row(r: &Row) -> Self {
         let sim: String = r.get_unwrap(0);
         let chr = sim.chars().count() == 1;
-        let idx = sim.chars().nth(0).unwrap_or('?');
         Self {
             sim,
             tra: r.get_unwrap(1),
@@ -28,101 +27,448 @@ impl Entry {
             mea: r.get_unwrap(3),
             hsk: r.get_unwrap(4),
             chr,
-            idx,
         }
     }
 
-    pub fn index(&self) -> char {
-        self.idx
+    /// Length of the simplified form in characters
+    pub fn len(&self) -> usize {
+        self.sim.chars().count()
+    }
+
+    pub fn sim(&self) -> &str {
+        &self.sim
+    }
+
+    pub fn tra(&self) -> &str {
+        &self.tra
+    }
+
+    pub fn pin(&self) -> &str {
+        &self.pin
+    }
+
+    pub fn mea(&self) -> &str {
+        &self.mea
+    }
+
+    pub fn hsk(&self) -> Option<u32> {
+        self.hsk
+    }
+
+    /// One line summary: headword, reading and the first sense
+    pub fn brief(&self) -> String {
+        let pin = pinyin::render(&self.pin, pinyin::style());
+        format!("{} [{}] {}", self.sim, pin, self.mea.split('/').next().unwrap_or_default())
+    }
+
+    /// 0 - a sense is exactly `q`, 1 - a sense starts with `q`, 2 - `q` is somewhere in the meaning
+    fn gloss_rank(&self, q: &str) -> u32 {
+        let mut rank = 2;
+        for g in self.mea.split('/').map(gloss) {
+            if g == q {
+                return 0;
+            }
+            if g.starts_with(q) && g[q.len()..].starts_with([' ', ',', ';']) {
+                rank = 1;
+            }
+        }
+        rank
+    }
+}
+
+/// Which headword form a lookup matched
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Form {
+    Simplified,
+    Traditional,
+    Both,
+}
+
+impl Form {
+    /// The form closer to `s`, for lookups that only matched after conversion
+    fn closest(e: &Entry, s: &str) -> Self {
+        let same = |w: &str| w.chars().zip(s.chars()).filter(|(a, b)| a == b).count();
+        match same(&e.sim).cmp(&same(&e.tra)) {
+            std::cmp::Ordering::Greater => Self::Simplified,
+            std::cmp::Ordering::Less => Self::Traditional,
+            std::cmp::Ordering::Equal => Self::Both,
+        }
+    }
+}
+
+/// An entry displayed with the matched form marked
+pub struct Matched<'a>(pub &'a Entry, pub Form);
+
+impl Entry {
+    fn write(&self, f: &mut fmt::Formatter, form: Form) -> fmt::Result {
+        let hsk = if self.hsk.is_some() { format!("HSK{}", self.hsk.unwrap()) } else { String::new() };
+        let pin = pinyin::render(&self.pin, pinyin::style());
+        let (sim, tra) = match form {
+            Form::Simplified => (format!("【{}】", self.sim), self.tra.clone()),
+            Form::Traditional => (self.sim.clone(), format!("【{}】", self.tra)),
+            Form::Both => (self.sim.clone(), self.tra.clone()),
+        };
+        write!(f, "- {} | {} [{}] {}\n- {}", sim, tra, pin, hsk, self.mea.replace("/","\n- "))
     }
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let hsk = if self.hsk.is_some() { format!("HSK{}", self.hsk.unwrap()) } else { String::new() };
-        write!(f, "- {} | {} [{}] {}\n- {}", self.sim, self.tra, self.pin, hsk, self.mea.replace("/","\n- "))
+        self.write(f, Form::Both)
+    }
+}
+
+impl fmt::Display for Matched<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write(f, self.1)
+    }
+}
+
+/// A sense reduced for comparison: lowercase, no notes in brackets, no leading "to"
+fn gloss(s: &str) -> String {
+    let mut res = String::new();
+    let mut depth = 0;
+    for c in s.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 => res.extend(c.to_lowercase()),
+            _ => (),
+        }
+    }
+    let res = res.split_whitespace().collect::<Vec<&str>>().join(" ");
+    res.strip_prefix("to ").map(str::to_string).unwrap_or(res)
+}
+
+/// Character trie over headwords, node 0 is the root
+struct Trie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    next: Vec<(char, usize)>,
+    words: Vec<usize>,
+}
+
+impl Trie {
+    fn new() -> Self {
+        Self { nodes: vec![TrieNode::default()] }
+    }
+
+    fn child(&self, n: usize, c: char) -> Option<usize> {
+        let next = &self.nodes[n].next;
+        next.binary_search_by_key(&c, |x| x.0).ok().map(|i| next[i].1)
+    }
+
+    fn insert(&mut self, key: &str, idx: usize) {
+        let mut n = 0;
+        for c in key.chars() {
+            n = match self.nodes[n].next.binary_search_by_key(&c, |x| x.0) {
+                Ok(i) => self.nodes[n].next[i].1,
+                Err(i) => {
+                    let m = self.nodes.len();
+                    self.nodes.push(TrieNode::default());
+                    self.nodes[n].next.insert(i, (c, m));
+                    m
+                }
+            };
+        }
+        self.nodes[n].words.push(idx);
+    }
+
+    /// Words on the path of `s`, shortest first
+    fn prefixes(&self, s: &[char]) -> Vec<usize> {
+        let mut res = vec![];
+        let mut n = 0;
+        for c in s {
+            match self.child(n, *c) {
+                Some(m) => n = m,
+                None => break,
+            }
+            res.extend(&self.nodes[n].words);
+        }
+        res
+    }
+
+    /// Words starting with `prefix`, shortest first
+    fn starting_with(&self, prefix: &str, limit: usize) -> Vec<usize> {
+        let mut n = 0;
+        for c in prefix.chars() {
+            match self.child(n, c) {
+                Some(m) => n = m,
+                None => return vec![],
+            }
+        }
+        let mut res = vec![];
+        let mut queue = std::collections::VecDeque::from([n]);
+        while let Some(n) = queue.pop_front() {
+            res.extend(&self.nodes[n].words);
+            if res.len() >= limit {
+                res.truncate(limit);
+                break;
+            }
+            queue.extend(self.nodes[n].next.iter().map(|x| x.1));
+        }
+        res
     }
 }
 
+/// Lowercase words of an English definition
+fn words(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+}
+
 pub struct Cedict {
-    data_t: BTreeMap<char, Vec<Entry>>,
+    entries: Vec<Entry>,
+    /// Simplified headword -> entries
+    sim: HashMap<String, Vec<usize>>,
+    /// Traditional headword -> entries
+    tra: HashMap<String, Vec<usize>>,
+    /// Reading without tones and spaces -> entries, ordered for prefix search
+    pin: BTreeMap<String, Vec<usize>>,
+    /// English word -> entries with that word in the definition
+    eng: HashMap<String, Vec<usize>>,
+    /// Character -> entries containing it
+    chars: HashMap<char, Vec<usize>>,
+    /// Simplified and traditional headwords
+    trie: Trie,
+    characters: Vec<usize>,
+    conv: Converter,
 }
 
 impl Cedict {
+    pub const MAX_RESULTS: usize = 50;
+
     pub fn new(fname: &str) -> Dupa<Self> {
         let conn = Connection::open_with_flags(fname, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
         let mut st = conn.prepare("SELECT * from Cedict")?;
         
-        let mut data_t: BTreeMap<char, Vec<Entry>> = BTreeMap::new();
+        let mut entries = vec![];
         let mut data_tr = st.query([])?;
         while let Ok(next) = data_tr.next() {
             if let Some(row) = next {
-                let e = Entry::from_row(row);
-                let k = e.index();
-                data_t.entry(k).or_default().push(e);
+                entries.push(Entry::from_row(row));
             } else {
                 break;
             }
         }
-        Ok(Self { 
-            data_t,
-        })
+        Ok(Self::from_entries(entries))
     }
 
-    pub fn characters(&self) -> Vec<&Entry> {
-        self.data_t.par_iter()
-            .map(|(_,v)| v.iter().filter(|&e| e.chr).collect())
-            .reduce(|| vec![], |a,b| ([a,b]).concat() )
+    /// Dictionary of `(simplified, traditional, pinyin, meaning)` rows
+    #[cfg(test)]
+    pub fn from_rows(rows: &[(&str, &str, &str, &str)]) -> Self {
+        let entries = rows.iter()
+            .map(|&(sim, tra, pin, mea)| Entry {
+                sim: sim.to_string(),
+                tra: tra.to_string(),
+                pin: pin.to_string(),
+                mea: mea.to_string(),
+                hsk: None,
+                chr: sim.chars().count() == 1,
+            })
+            .collect();
+        Self::from_entries(entries)
+    }
+
+    fn from_entries(entries: Vec<Entry>) -> Self {
+        let mut sim: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut tra: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut pin: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        let mut eng: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut chars: HashMap<char, Vec<usize>> = HashMap::new();
+        let mut trie = Trie::new();
+        let mut characters = vec![];
+
+        for (i, e) in entries.iter().enumerate() {
+            sim.entry(e.sim.clone()).or_default().push(i);
+            tra.entry(e.tra.clone()).or_default().push(i);
+            pin.entry(toneless(&e.pin)).or_default().push(i);
+            let mut ws: Vec<String> = words(&e.mea).collect();
+            ws.sort();
+            ws.dedup();
+            for w in ws {
+                eng.entry(w).or_default().push(i);
+            }
+            let mut cs: Vec<char> = e.sim.chars().chain(e.tra.chars()).collect();
+            cs.sort();
+            cs.dedup();
+            for c in cs {
+                chars.entry(c).or_default().push(i);
+            }
+            trie.insert(&e.sim, i);
+            if e.tra != e.sim {
+                trie.insert(&e.tra, i);
+            }
+            if e.chr {
+                characters.push(i);
+            }
+        }
+        debug!("Indexed {} entries, {} trie nodes", entries.len(), trie.nodes.len());
+
+        let conv = Converter::new(entries.iter());
+        Self {
+            entries,
+            sim,
+            tra,
+            pin,
+            eng,
+            chars,
+            trie,
+            characters,
+            conv,
+        }
     }
 
-    fn characters_filtered(&self, s: &str) -> Vec<&Entry> {
-        self.data_t.par_iter()
-            .map(|(_,v)| v.iter().filter(|&e| e.chr && (s.contains(e.sim.as_str()) || s.contains(e.tra.as_str())) ).collect())
-            .reduce(|| vec![], |a,b| ([a,b]).concat() )
+    fn get(&self, idx: &[usize]) -> Vec<&Entry> {
+        idx.iter().map(|&i| &self.entries[i]).collect()
+    }
 
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn characters(&self) -> Vec<&Entry> {
+        self.get(&self.characters)
     }
 
     /// Convert traditional to simplified
     pub fn to_sim(&self, s: &str) -> String {
-        let chr_list = self.characters_filtered(s);
-        let ss = s.par_chars()
-            .map(|z| {
-                let c = chr_list.iter().find(|x| x.tra == z.to_string());
-                match c {
-                    Some(c) => c.sim.chars().nth(0).unwrap_or(z),
-                    None => z,
-                }
-                
-            }).collect();
-        ss
+        self.conv.to_sim(s)
+    }
+
+    /// Convert simplified to traditional
+    pub fn to_tra(&self, s: &str) -> String {
+        self.conv.to_tra(s)
     }
 
     pub fn len(&self) -> usize {
-        self.data_t.len()
+        self.entries.len()
     }
 
     /// Search all containing
     pub fn search(&self, s: &str) -> Vec<&Entry> {
-        self.data_t.par_iter()
-            .map(|(_,v)| v.iter().filter(|&e| e.sim.contains(s)).collect() )
-            .reduce(|| vec![], |a,b| ([a,b]).concat() )
+        let Some(c) = s.chars().next() else {
+            return vec![];
+        };
+        match self.chars.get(&c) {
+            Some(r) => r.iter()
+                .map(|&i| &self.entries[i])
+                .filter(|e| e.sim.contains(s))
+                .collect(),
+            None => vec![],
+        }
     }
 
-    /// Search exact match
-    pub fn find(&self, s: &str) -> Vec<&Entry> {
-        if s.is_empty() {
+    /// Search all starting with `s`, shortest first
+    pub fn prefix(&self, s: &str, limit: usize) -> Vec<&Entry> {
+        let mut idx = self.trie.starting_with(s, limit);
+        let mut seen = HashSet::new();
+        idx.retain(|i| seen.insert(*i));
+        self.get(&idx)
+    }
+
+    /// Search English definitions for whole words, best matches first
+    pub fn search_english(&self, s: &str) -> Vec<&Entry> {
+        let q = gloss(s);
+        let qw: Vec<String> = words(&q).collect();
+        if qw.is_empty() {
             return vec![];
         }
-        debug!("find: {}", s);
-        let c = s.chars().nth(0).unwrap();
-        if let Some(r) = self.data_t.get(&c) {
-            return r.iter()
-                .filter(|e| e.sim.as_str() == s)
-                .collect::<Vec<&Entry>>();
+        // Candidates come from the rarest word of the query
+        let cand = qw.iter()
+            .map(|w| self.eng.get(w).map_or(&[][..], |v| v.as_slice()))
+            .min_by_key(|v| v.len())
+            .unwrap_or(&[]);
+        let mut res: Vec<(u32, &Entry)> = cand.iter()
+            .map(|&i| &self.entries[i])
+            .filter(|e| words(&e.mea).collect::<Vec<String>>().windows(qw.len()).any(|w| w == qw.as_slice()))
+            .map(|e| (e.gloss_rank(&q), e))
+            .collect();
+        res.sort_by_key(|(r, e)| (*r, e.hsk.unwrap_or(u32::MAX), e.mea.len()));
+        res.into_iter()
+            .take(Self::MAX_RESULTS)
+            .map(|(_, e)| e)
+            .collect()
+    }
+
+    /// Search readings, `zhongguo`, `zhong1guo2` and `zhong guo` all find 中国
+    pub fn search_pinyin(&self, s: &str) -> Vec<&Entry> {
+        self.pinyin_matches(s).into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// Readings starting with the query, with whether the whole reading matched, whole ones first
+    fn pinyin_matches(&self, s: &str) -> Vec<(bool, &Entry)> {
+        let q = pinyin::compact(s);
+        let t = toneless(&q);
+        if t.is_empty() {
+            return vec![];
         }
-        vec![]
+        let mut res: Vec<(bool, &Entry)> = self.pin.range(t.clone()..)
+            .take_while(|(k, _)| k.starts_with(t.as_str()))
+            .flat_map(|(_, v)| v.iter().map(|&i| &self.entries[i]))
+            .filter_map(|e| pinyin::match_compact(&q, &pinyin::compact(&e.pin)).map(|m| (m, e)))
+            .collect();
+        res.sort_by_key(|(m, e)| (!*m, e.hsk.unwrap_or(u32::MAX), e.len()));
+        res.truncate(Self::MAX_RESULTS);
+        res
     }
 
+    /// Latin input read both ways: whole pinyin readings, then English definitions,
+    /// then readings that only start with the input ("he" -> hen, hei...)
+    pub fn search_latin(&self, s: &str) -> Vec<&Entry> {
+        let (whole, prefix): (Vec<_>, Vec<_>) = if pinyin::is_pinyin(s) {
+            self.pinyin_matches(s).into_iter().partition(|(m, _)| *m)
+        } else {
+            (vec![], vec![])
+        };
+        whole.into_iter().map(|(_, e)| e)
+            .chain(self.search_english(s))
+            .chain(prefix.into_iter().map(|(_, e)| e))
+            .collect()
+    }
 
+    /// Search exact match
+    pub fn find(&self, s: &str) -> Vec<&Entry> {
+        self.lookup(s).into_iter().map(|m| m.0).collect()
+    }
+
+    /// Exact match in simplified, traditional or mixed script
+    pub fn lookup(&self, s: &str) -> Vec<Matched<'_>> {
+        debug!("lookup: {}", s);
+        let mut res: Vec<(usize, Form)> = vec![];
+        let mut add = |idx: Option<&Vec<usize>>, form: &dyn Fn(&Entry) -> Form| {
+            for &i in idx.into_iter().flatten() {
+                if !res.iter().any(|r| r.0 == i) {
+                    res.push((i, form(&self.entries[i])));
+                }
+            }
+        };
+        let exact = |e: &Entry| if e.sim == e.tra { Form::Both } else if e.sim == s { Form::Simplified } else { Form::Traditional };
+        add(self.sim.get(s), &exact);
+        add(self.tra.get(s), &exact);
+        add(self.sim.get(&self.to_sim(s)), &|e| Form::closest(e, s));
+        add(self.tra.get(&self.to_tra(s)), &|e| Form::closest(e, s));
+        res.into_iter().map(|(i, f)| Matched(&self.entries[i], f)).collect()
+    }
+
+    /// Search exact match of the traditional form
+    pub fn find_tra(&self, s: &str) -> Vec<&Entry> {
+        self.tra.get(s).map_or(vec![], |v| self.get(v))
+    }
+
+    /// Entries whose simplified or traditional form is a prefix of `s`
+    pub fn prefixes(&self, s: &[char]) -> Vec<&Entry> {
+        let mut idx = self.trie.prefixes(s);
+        idx.dedup();
+        self.get(&idx)
+    }
+}
 
+/// Compact reading without tone numbers
+fn toneless(s: &str) -> String {
+    pinyin::compact(s).chars().filter(|c| !c.is_ascii_digit()).collect()
 }
@@ -1,6 +1,3 @@
-use openai::{
-    chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole}, Credentials
-};
 use iced::task::{Never, Sipper, sipper};
 use std::sync::Arc;
 use deepl::DeepLApi;
@@ -8,105 +5,223 @@ use anyhow::{Result, anyhow};
 use paddleocr_rs::{Det, Rec};
 use elevenlabs_rs::*;
 use crate::make_enum;
+use crate::config::AiChatConfiguration;
+use crate::provider::{self, Provider};
+use crate::sse::EventStream;
 use tracing::{debug, error, info};
-use tokio::sync::mpsc::error::TryRecvError;
 
-make_enum!(AiChat, [ChatGPT, Deepseek, Grok, Ollama]);
+make_enum!(Role, [System, User, Assistant]);
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+    /// Thoughts of a reasoning model before its answer, not sent back
+    pub reasoning: String,
+}
+
+impl Turn {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self { role, content: content.into(), reasoning: String::new() }
+    }
+}
+
+/// The whole conversation is sent, the answer comes back as `Event`s with the same id
 pub struct ChatPrompt {
+    pub id: u64,
     pub chat_name: String,
-    pub prompt: String,
+    pub messages: Vec<Turn>,
 }
 
-#[derive(Debug, Clone)]
+pub enum ChatRequest {
+    /// Aborts the answer being streamed, if any
+    Prompt(ChatPrompt),
+    Cancel,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
-    MessageReceived(String),
-    Error(String),
-    End,
+    MessageReceived(u64, String),
+    ReasoningReceived(u64, String),
+    Error(u64, String),
+    End(u64),
+    Cancelled(u64),
 }
 
-/// Return (URL, model, key)
-fn get_ai_conf(chat: &str) -> Option<(&String, &String, &String)> {
-    let conf = crate::CONFIG.get().unwrap();
-    let chat_conf = conf.ai_chats.get(chat);
+/// A request received while answering stops the answer, a new prompt is answered next
+fn interrupt(r: Result<ChatRequest, async_channel::RecvError>) -> Option<ChatPrompt> {
+    match r {
+        Ok(ChatRequest::Prompt(p)) => Some(p),
+        _ => None,
+    }
+}
+
+/// Separates the `<think>…</think>` blocks of reasoning models from the answer,
+/// a tag can be split between two chunks
+#[derive(Default)]
+struct ThinkTags {
+    thinking: bool,
+    /// End of the last chunk that may be the start of a tag
+    pending: String,
+    /// Whitespace before the answer is dropped
+    answered: bool,
+}
+
+impl ThinkTags {
+    /// Answer and reasoning in the chunk
+    fn split(&mut self, chunk: &str) -> (String, String) {
+        let mut s = std::mem::take(&mut self.pending) + chunk;
+        let (mut answer, mut reasoning) = (String::new(), String::new());
+        loop {
+            let tag = if self.thinking { "</think>" } else { "<think>" };
+            let out = if self.thinking { &mut reasoning } else { &mut answer };
+            match s.find(tag) {
+                Some(p) => {
+                    out.push_str(&s[..p]);
+                    s.replace_range(..p + tag.len(), "");
+                    self.thinking = !self.thinking;
+                }
+                None => {
+                    let keep = (1..tag.len()).rev().find(|n| s.ends_with(&tag[..*n])).unwrap_or(0);
+                    self.pending = s.split_off(s.len() - keep);
+                    out.push_str(&s);
+                    break;
+                }
+            }
+        }
+        self.answer(answer, reasoning)
+    }
 
-    if let Some(chat_conf) = chat_conf {
-        Some( (&chat_conf.url, &chat_conf.model, &chat_conf.key) )
-    } else {
-        error!("Invalid AI configuration, it shouldn'e even be here");
-        None
+    /// What was held back at the end of the answer
+    fn finish(&mut self) -> (String, String) {
+        let rest = std::mem::take(&mut self.pending);
+        if self.thinking { self.answer(String::new(), rest) } else { self.answer(rest, String::new()) }
+    }
+
+    fn answer(&mut self, answer: String, reasoning: String) -> (String, String) {
+        if self.answered {
+            return (answer, reasoning);
+        }
+        let answer = answer.trim_start().to_string();
+        self.answered = !answer.is_empty();
+        (answer, reasoning)
+    }
+}
+
+fn events(id: u64, (answer, reasoning): (String, String)) -> Vec<Event> {
+    let mut res = vec![];
+    if !reasoning.is_empty() {
+        res.push(Event::ReasoningReceived(id, reasoning));
+    }
+    if !answer.is_empty() {
+        debug!("Received chat content: {}", answer);
+        res.push(Event::MessageReceived(id, answer));
+    }
+    res
+}
+
+/// Starts a streamed answer
+async fn completion(conf: &AiChatConfiguration, provider: &dyn Provider, messages: &[Turn]) -> Result<EventStream> {
+    let client = provider::client(conf)?;
+    let resp = provider.request(&client, messages).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(anyhow!("{}: {}", status, resp.text().await.unwrap_or_default()));
+    }
+    Ok(provider.events(resp))
+}
+
+/// Streams the answer to `prompt` through `send`, a request arriving meanwhile stops it.
+/// Returns the prompt that stopped it, to be answered next.
+async fn stream_answer(prompt: ChatPrompt, conf: Option<&AiChatConfiguration>,
+    recv: &async_channel::Receiver<ChatRequest>, send: &mut impl AsyncFnMut(Event)) -> Option<ChatPrompt> {
+    let id = prompt.id;
+    info!("Ai: {}", prompt.chat_name);
+    let Some(conf) = conf else {
+        error!("Failed to get chat configuration");
+        send(Event::Error(id, format!("No configuration for {}", prompt.chat_name))).await;
+        return None;
+    };
+    debug!("Messages: {}", prompt.messages.len());
+    let provider = provider::provider(conf);
+
+    let stream = tokio::select! {
+        s = completion(conf, provider.as_ref(), &prompt.messages) => s,
+        r = recv.recv() => {
+            info!("Chat cancelled");
+            send(Event::Cancelled(id)).await;
+            return interrupt(r);
+        }
+    };
+    let mut stream = match stream {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Error requesting: {}", e.to_string());
+            send(Event::Error(id, e.to_string())).await;
+            return None;
+        }
+    };
+
+    // Each chunk is passed on as soon as it arrives
+    let mut tags = ThinkTags::default();
+    let mut next = None;
+    let end = loop {
+        tokio::select! {
+            data = stream.next() => match data {
+                Ok(Some(data)) => match provider.parse(&data) {
+                    Ok(Some(p)) => {
+                        let (answer, reasoning) = tags.split(&p.answer);
+                        for e in events(id, (answer, p.reasoning + &reasoning)) {
+                            send(e).await;
+                        }
+                    }
+                    Ok(None) => break Event::End(id),
+                    Err(e) => {
+                        error!("Chat error: {}", e);
+                        break Event::Error(id, e.to_string());
+                    }
+                },
+                Ok(None) => break Event::End(id),
+                Err(e) => {
+                    error!("Stream failed: {}", e);
+                    break Event::Error(id, e.to_string());
+                }
+            },
+            r = recv.recv() => {
+                next = interrupt(r);
+                info!("Chat cancelled");
+                break Event::Cancelled(id);
+            }
+        }
+    };
+    debug!("Finished streaming");
+    if let Event::End(_) = end {
+        for e in events(id, tags.finish()) {
+            send(e).await;
+        }
     }
+    send(end).await;
+    next
 }
 
 pub fn connect() -> impl Sipper<Never, Event> {
     sipper(async |mut output| {
+        let recv = crate::RECV.wait();
+        let mut next: Option<ChatPrompt> = None;
         loop {
-            let recv = crate::RECV.wait();
             // Receive prompt (pressed ask chat button)
-            if let Ok(prompt) = recv.recv().await {
-                info!("Received prompt");
-                let ai_chat = prompt.chat_name;
-                info!("Ai: {}", ai_chat);
-                if let Some((url, model, key)) = get_ai_conf(ai_chat.as_str()) {
-                    let prompt = prompt.prompt;
-                    debug!("Prompt: {}", prompt);
-                    debug!("Key {}", key);
-
-                    let c = Credentials::new(key, url);
-                    let messages = vec![ChatCompletionMessage {
-                        role: ChatCompletionMessageRole::User,
-                        content: Some(prompt),
-                        name: None,
-                        function_call: None,
-                        tool_calls: None,
-                        tool_call_id: None,
-                    }];
-                    let dur = std::time::Duration::from_millis(200);
-                    let cc = ChatCompletion::builder(model.as_str(), messages.clone())
-                        .credentials(c.clone())
-                        .stream(true)
-                        .create_stream()
-                        .await;
-
-                    match cc {
-                        Ok(mut cc) => {
-                            let mut d = true;
-                            while d {
-                                let r = cc.try_recv();
-                                match r {
-                                    Ok(r) => {
-                                        debug!("Got OK");
-                                        let choice = &r.choices[0];
-                                        if let Some(content) = &choice.delta.content {
-                                            debug!("Received chat content: {}", content);
-                                            output.send(Event::MessageReceived(content.clone())).await;
-                                        }
-                                    }
-                                    Err(TryRecvError::Empty) => {
-                                        debug!("Empty stream");
-                                        tokio::time::sleep(dur).await;
-                                    }
-                                    Err(TryRecvError::Disconnected) => {
-                                        debug!("** DC **");
-                                        d = false;
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Error requesting: {}", e.to_string());
-                            output.send(Event::Error(e.to_string())).await;
-                        }
-                    };
-
-                } else {
-                    error!("Failed to get chat configuration");
-                }
-
-            }
-            debug!("Finished streaming");
-            output.send(Event::End).await;
-        }    
+            let prompt = match next.take() {
+                Some(p) => p,
+                None => match recv.recv().await {
+                    Ok(ChatRequest::Prompt(p)) => p,
+                    Ok(ChatRequest::Cancel) => continue,
+                    Err(_) => std::future::pending().await,
+                },
+            };
+            info!("Received prompt");
+            let conf = crate::CONFIG.get().unwrap().ai_chats.get(&prompt.chat_name);
+            next = stream_answer(prompt, conf, recv, &mut async |e| output.send(e).await).await;
+        }
     })
 }
 
@@ -178,3 +293,137 @@ pub async fn el_play(text: Arc<String>) -> Result<elevenlabs_rs::Bytes> {
         Err(e) => Err(anyhow!(e.to_string())),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn chunk(content: &str) -> String {
+        format!("data: {}\n\n", serde_json::json!({"choices": [{"delta": {"content": content}}]}))
+    }
+
+    /// Events of the answer from a stand-in server sending `body` in pieces of 7 bytes,
+    /// `conf` gets the url of the server
+    async fn answer_with(conf: impl FnOnce(String) -> AiChatConfiguration, status: u16, body: &str) -> (Vec<Event>, mock::Request) {
+        let (url, req) = mock::serve(status, mock::pieces(body, 7)).await;
+        let conf = conf(url);
+        let (_sx, rx) = async_channel::unbounded();
+        let prompt = ChatPrompt { id: 7, chat_name: "test".into(), messages: vec![Turn::new(Role::User, "你好")] };
+        let mut res = vec![];
+        let next = stream_answer(prompt, Some(&conf), &rx, &mut async |e| res.push(e)).await;
+        assert!(next.is_none());
+        (res, req.await.unwrap())
+    }
+
+    async fn answer(status: u16, body: &str) -> (Vec<Event>, serde_json::Value) {
+        let conf = |url| AiChatConfiguration { url: format!("{}/v1", url), model: "m".into(), key: "k".into(), ..Default::default() };
+        let (events, req) = answer_with(conf, status, body).await;
+        (events, req.json())
+    }
+
+    async fn ollama(body: &str) -> (Vec<Event>, mock::Request) {
+        let conf = |url| AiChatConfiguration {
+            provider: Some("Ollama".into()),
+            url,
+            model: "qwen3".into(),
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+        answer_with(conf, 200, body).await
+    }
+
+    #[tokio::test]
+    async fn streams_until_done() {
+        let body = [chunk("你好"), chunk("，世界"), "data: [DONE]\n\n".to_string(), chunk("after the end")].concat();
+        let (events, req) = answer(200, &body).await;
+        assert_eq!(events, [
+            Event::MessageReceived(7, "你好".into()),
+            Event::MessageReceived(7, "，世界".into()),
+            Event::End(7),
+        ]);
+        assert_eq!(req["stream"], true);
+        assert_eq!(req["messages"], serde_json::json!([{"role": "user", "content": "你好"}]));
+    }
+
+    #[tokio::test]
+    async fn ollama_json_lines() {
+        let body = [
+            r#"{"message": {"role": "assistant", "content": "", "thinking": "想一想"}, "done": false}"#,
+            r#"{"message": {"role": "assistant", "content": "你好"}, "done": false}"#,
+            r#"{"message": {"role": "assistant", "content": ""}, "done": true}"#,
+            r#"{"message": {"role": "assistant", "content": "after the end"}, "done": false}"#,
+        ].join("\n");
+        let (events, req) = ollama(&body).await;
+        assert_eq!(events, [
+            Event::ReasoningReceived(7, "想一想".into()),
+            Event::MessageReceived(7, "你好".into()),
+            Event::End(7),
+        ]);
+        assert_eq!(req.path, "/api/chat");
+        let req = req.json();
+        assert_eq!(req["model"], "qwen3");
+        assert_eq!(req["stream"], true);
+        assert_eq!(req["options"]["num_predict"], 100);
+        assert_eq!(req["messages"], serde_json::json!([{"role": "user", "content": "你好"}]));
+    }
+
+    #[tokio::test]
+    async fn ollama_error() {
+        let body = r#"{"message": {"role": "assistant", "content": "你"}, "done": false}
+{"error": "model is out of memory"}
+"#;
+        let (events, _) = ollama(body).await;
+        assert_eq!(events, [
+            Event::MessageReceived(7, "你".into()),
+            Event::Error(7, "model is out of memory".into()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn reasoning_content() {
+        let thought = format!("data: {}\n\n", serde_json::json!({"choices": [{"delta": {"reasoning_content": "想一想", "content": null}}]}));
+        let body = [thought, chunk("\n好"), "data: [DONE]\n\n".to_string()].concat();
+        let (events, _) = answer(200, &body).await;
+        assert_eq!(events, [
+            Event::ReasoningReceived(7, "想一想".into()),
+            Event::MessageReceived(7, "好".into()),
+            Event::End(7),
+        ]);
+    }
+
+    /// Answer and reasoning of `chunks` passed through `ThinkTags`
+    fn think(chunks: &[&str]) -> (String, String) {
+        let mut tags = ThinkTags::default();
+        let (mut answer, mut reasoning) = (String::new(), String::new());
+        for c in chunks {
+            let (a, r) = tags.split(c);
+            answer.push_str(&a);
+            reasoning.push_str(&r);
+        }
+        let (a, r) = tags.finish();
+        (answer + &a, reasoning + &r)
+    }
+
+    #[test]
+    fn think_tags_split_between_chunks() {
+        assert_eq!(think(&["<thi", "nk>想一想</th", "ink>\n\nanswer"]), ("answer".into(), "想一想".into()));
+        assert_eq!(think(&["<", "think>a<", "/think>", " b"]), ("b".into(), "a".into()));
+    }
+
+    #[test]
+    fn think_tags_flush_at_the_end() {
+        assert_eq!(think(&["a <"]), ("a <".into(), "".into()));
+        assert_eq!(think(&["<think>a</thi"]), ("".into(), "a</thi".into()));
+        assert_eq!(think(&["1 < 2", " <b>"]), ("1 < 2 <b>".into(), "".into()));
+    }
+
+    #[tokio::test]
+    async fn error_status() {
+        let (events, _) = answer(401, r#"{"error": {"message": "Invalid key"}}"#).await;
+        match events.as_slice() {
+            [Event::Error(7, e)] => assert!(e.starts_with("401") && e.contains("Invalid key"), "{}", e),
+            e => panic!("{:?}", e),
+        }
+    }
+}
@@ -20,14 +20,95 @@ pub struct Window {
     pub theme: String,
     pub font: String,
     pub padding: u16,
+    /// Numbered, Marked or Zhuyin
+    pub pinyin: Option<String>,
+    /// Colouring of words in the text: Off, Anki or Hsk
+    pub marks: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct AiChatConfiguration {
     pub name: String,
+    /// OpenAI for any OpenAI compatible endpoint (the default) or Ollama for its native API
+    pub provider: Option<String>,
+    #[serde(default)]
     pub key: String,
     pub url: String,
     pub model: String,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    /// Replaces `chat_system` for this chat
+    pub system: Option<String>,
+    /// Seconds to wait for a connection and then for each part of the answer
+    pub timeout: Option<u64>,
+    /// Sent with every request, e.g. an organization or a proxy key
+    pub headers: Option<BTreeMap<String, String>>,
+}
+
+/// Where "Add to Anki" creates notes through AnkiConnect
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct AnkiNote {
+    /// Defaults to http://127.0.0.1:8765
+    pub url: Option<String>,
+    pub deck: String,
+    pub note_type: String,
+    pub tags: Option<Vec<String>>,
+
+    /// Names of the note fields, unset ones are left empty
+    pub word_field: String,
+    pub reading_field: Option<String>,
+    pub meaning_field: Option<String>,
+    pub sentence_field: Option<String>,
+    pub context_field: Option<String>,
+}
+
+/// A chat button, placeholders: {word}, {sentence}, {paragraph}, {pinyin}, {definition}, {text}
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub template: String,
+}
+
+/// One Anki collection or several, e.g. of different profiles
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Collections {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for Collections {
+    fn default() -> Self {
+        Self::Many(vec![])
+    }
+}
+
+impl Collections {
+    pub fn paths(&self) -> Vec<String> {
+        match self {
+            Self::One(p) => vec![shellexpand::tilde(p).to_string()],
+            Self::Many(p) => p.iter().map(|p| shellexpand::tilde(p).to_string()).collect(),
+        }
+    }
+}
+
+/// Export of unknown words to a file Anki can import
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct AnkiExport {
+    /// Columns in order: Word, Traditional, Reading, Meaning, Sentence, Hsk
+    pub fields: Vec<String>,
+    /// Default to the ones of `anki_note`, for .apkg files the note type is created
+    pub deck: Option<String>,
+    pub note_type: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Field positions (from 0) of a note type in the Anki collection
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct FieldMap {
+    pub word: usize,
+    pub reading: Option<usize>,
+    pub meaning: Option<usize>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -35,10 +116,24 @@ pub struct Config {
     pub ocr_models: String,
     pub api_keys: Keys,
     pub window: Window,
-    pub anki: String,
+    pub anki: Collections,
+    /// Only notes with cards in these decks or their subdecks are used
+    pub anki_decks: Option<Vec<String>>,
+    pub anki_note: Option<AnkiNote>,
+    pub anki_export: Option<AnkiExport>,
+    /// Keyed by note type name or id, "default" for the rest
+    pub anki_fields: Option<BTreeMap<String, FieldMap>>,
+    /// Days of review interval after which a word is marked as known, 21 by default
+    pub known_interval: Option<i64>,
+    /// Seconds between checks for changes in the collection, 0 turns reloading off
+    pub anki_reload: Option<u64>,
 
     pub voice: String,
     pub sel_chat: String,
+    /// System prompt of chat conversations, the text being read is added to it
+    pub chat_system: Option<String>,
+    /// Chat buttons, the Meaning, Examples and Synonyms prompts when not set
+    pub prompts: Option<Vec<PromptTemplate>>,
 
     pub rec_min_score: Option<f32>,
 
@@ -0,0 +1,124 @@
+use crate::chat::{Role, Turn};
+
+/// A chat about the text being read. Branches share the system prompt,
+/// a new one starts as a copy of the current branch without its last exchange.
+#[derive(Clone, Debug, Default)]
+pub struct Conversation {
+    system: String,
+    branches: Vec<Vec<Turn>>,
+    current: usize,
+    /// Branch waiting for the rest of an answer and the id of its request
+    streaming: Option<(usize, u64)>,
+}
+
+impl Conversation {
+    pub const DEFAULT_SYSTEM: &'static str = "You are a Chinese teacher helping a learner read a Chinese text. Answer briefly, give pinyin for the Chinese words you use.";
+    /// Longer texts are cut, in characters
+    const TEXT_LIMIT: usize = 6000;
+
+    pub fn new(system: &str, text: &str) -> Self {
+        let mut system = system.to_string();
+        let text = text.trim();
+        if !text.is_empty() {
+            system.push_str("\n\nThe text the learner is reading:\n");
+            system.extend(text.chars().take(Self::TEXT_LIMIT));
+        }
+        Self { system, branches: vec![vec![]], current: 0, streaming: None }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.branches.iter().all(|b| b.is_empty())
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.streaming.is_some()
+    }
+
+    pub fn turns(&self) -> &[Turn] {
+        self.branches.get(self.current).map_or(&[], |b| b.as_slice())
+    }
+
+    /// Adds the question to the current branch, returns the messages to send.
+    /// An answer still being streamed stays as it is.
+    pub fn ask(&mut self, question: &str, id: u64) -> Vec<Turn> {
+        if self.branches.is_empty() {
+            self.branches.push(vec![]);
+        }
+        let b = &mut self.branches[self.current];
+        b.push(Turn::new(Role::User, question));
+        let res = std::iter::once(Turn::new(Role::System, self.system.as_str()))
+            .chain(b.iter().cloned())
+            .collect();
+        b.push(Turn::new(Role::Assistant, ""));
+        self.streaming = Some((self.current, id));
+        res
+    }
+
+    /// Appends a part of the answer to request `id`, true if it belongs to the branch on display
+    pub fn receive(&mut self, id: u64, chunk: &str) -> bool {
+        self.append(id, |t| t.content.push_str(chunk))
+    }
+
+    /// Appends a part of the reasoning before the answer to request `id`
+    pub fn think(&mut self, id: u64, chunk: &str) -> bool {
+        self.append(id, |t| t.reasoning.push_str(chunk))
+    }
+
+    fn append(&mut self, id: u64, f: impl FnOnce(&mut Turn)) -> bool {
+        let Some((i, _)) = self.streaming.filter(|s| s.1 == id) else { return false };
+        if let Some(t) = self.branches[i].last_mut().filter(|t| t.role == Role::Assistant) {
+            f(t);
+        }
+        i == self.current
+    }
+
+    /// Reasoning behind the last answer on display
+    pub fn reasoning(&self) -> Option<&str> {
+        self.turns().iter()
+            .rfind(|t| t.role == Role::Assistant)
+            .map(|t| t.reasoning.trim())
+            .filter(|r| !r.is_empty())
+    }
+
+    /// The answer to `id` is complete or was cancelled
+    pub fn end(&mut self, id: u64) {
+        if self.streaming.is_some_and(|s| s.1 == id) {
+            self.streaming = None;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.streaming = None;
+    }
+
+    /// Copy of the current branch without its last question and answer
+    pub fn branch(&mut self) {
+        let mut b = self.turns().to_vec();
+        if let Some(p) = b.iter().rposition(|t| t.role == Role::User) {
+            b.truncate(p);
+        }
+        self.branches.push(b);
+        self.current = self.branches.len() - 1;
+    }
+
+    pub fn switch(&mut self, i: usize) {
+        if i < self.branches.len() {
+            self.current = i;
+        }
+    }
+
+    /// Index of the branch on display and the number of branches
+    pub fn position(&self) -> (usize, usize) {
+        (self.current, self.branches.len())
+    }
+
+    pub fn transcript(&self) -> String {
+        self.turns().iter()
+            .map(|t| match t.role {
+                Role::User => format!("> {}", t.content),
+                _ => t.content.clone(),
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+}
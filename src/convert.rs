@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use crate::cedict::Entry;
+
+/// Traditional <-> simplified conversion built from CEDICT headwords.
+/// Phrases are matched longest first, single characters take the mapping
+/// seen most often across all entries.
+pub struct Converter {
+    t2s: HashMap<char, char>,
+    s2t: HashMap<char, char>,
+    t2s_phrase: HashMap<String, String>,
+    s2t_phrase: HashMap<String, String>,
+    max_len: usize,
+}
+
+/// Most frequent target for every source character
+fn best(counts: HashMap<(char, char), usize>) -> HashMap<char, char> {
+    let mut res: HashMap<char, (char, usize)> = HashMap::new();
+    for ((a, b), n) in counts {
+        let e = res.entry(a).or_insert((b, n));
+        if n > e.1 || (n == e.1 && b < e.0) {
+            *e = (b, n);
+        }
+    }
+    res.into_iter().map(|(a, (b, _))| (a, b)).collect()
+}
+
+fn by_char(s: &str, map: &HashMap<char, char>) -> String {
+    s.chars().map(|c| *map.get(&c).unwrap_or(&c)).collect()
+}
+
+impl Converter {
+    pub fn new<'a>(entries: impl Iterator<Item = &'a Entry> + Clone) -> Self {
+        let mut t2s_n: HashMap<(char, char), usize> = HashMap::new();
+        let mut s2t_n: HashMap<(char, char), usize> = HashMap::new();
+        for e in entries.clone() {
+            if e.sim().chars().count() != e.tra().chars().count() {
+                continue;
+            }
+            for (s, t) in e.sim().chars().zip(e.tra().chars()) {
+                *t2s_n.entry((t, s)).or_default() += 1;
+                *s2t_n.entry((s, t)).or_default() += 1;
+            }
+        }
+        let t2s = best(t2s_n);
+        let s2t = best(s2t_n);
+
+        // Only phrases which the character tables would get wrong are kept
+        let mut t2s_phrase: HashMap<String, (String, bool)> = HashMap::new();
+        let mut s2t_phrase: HashMap<String, (String, bool)> = HashMap::new();
+        let mut max_len = 1;
+        for e in entries.filter(|e| e.len() > 1 && e.sim().chars().count() == e.tra().chars().count()) {
+            let hsk = e.hsk().is_some();
+            if by_char(e.tra(), &t2s) != e.sim() {
+                let p = t2s_phrase.entry(e.tra().to_string()).or_insert((e.sim().to_string(), hsk));
+                if hsk && !p.1 {
+                    *p = (e.sim().to_string(), hsk);
+                }
+                max_len = max_len.max(e.len());
+            }
+            if by_char(e.sim(), &s2t) != e.tra() {
+                let p = s2t_phrase.entry(e.sim().to_string()).or_insert((e.tra().to_string(), hsk));
+                if hsk && !p.1 {
+                    *p = (e.tra().to_string(), hsk);
+                }
+                max_len = max_len.max(e.len());
+            }
+        }
+
+        Self {
+            t2s,
+            s2t,
+            t2s_phrase: t2s_phrase.into_iter().map(|(k, v)| (k, v.0)).collect(),
+            s2t_phrase: s2t_phrase.into_iter().map(|(k, v)| (k, v.0)).collect(),
+            max_len,
+        }
+    }
+
+    fn convert(&self, s: &str, phrases: &HashMap<String, String>, chars: &HashMap<char, char>) -> String {
+        let idx: Vec<usize> = s.char_indices().map(|(i, _)| i).chain([s.len()]).collect();
+        let n = idx.len() - 1;
+        let mut res = String::with_capacity(s.len());
+        let mut i = 0;
+        'outer: while i < n {
+            for l in (2..=self.max_len.min(n - i)).rev() {
+                if let Some(p) = phrases.get(&s[idx[i]..idx[i + l]]) {
+                    res.push_str(p);
+                    i += l;
+                    continue 'outer;
+                }
+            }
+            let c = s[idx[i]..].chars().next().unwrap();
+            res.push(*chars.get(&c).unwrap_or(&c));
+            i += 1;
+        }
+        res
+    }
+
+    pub fn to_sim(&self, s: &str) -> String {
+        self.convert(s, &self.t2s_phrase, &self.t2s)
+    }
+
+    pub fn to_tra(&self, s: &str) -> String {
+        self.convert(s, &self.s2t_phrase, &self.s2t)
+    }
+}
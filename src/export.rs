@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::Path;
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde_json::json;
+use crate::config::{AnkiExport, AnkiNote};
+use crate::helper;
+use crate::report::{Report, WordStat};
+use crate::{make_enum, Dupa};
+
+make_enum!(Column, [Word, Traditional, Reading, Meaning, Sentence, Hsk]);
+
+/// Unknown words of a text as notes for Anki
+pub struct Export {
+    columns: Vec<Column>,
+    deck: Option<String>,
+    note_type: Option<String>,
+    /// Note type of `anki_note`, used by text files when `note_type` is not set
+    existing_type: Option<String>,
+    tags: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+/// Old collection schema (11), still read by the .apkg importer
+const SCHEMA: &str = "
+CREATE TABLE col (id integer primary key, crt integer not null, mod integer not null, scm integer not null, ver integer not null, dty integer not null, usn integer not null, ls integer not null, conf text not null, models text not null, decks text not null, dconf text not null, tags text not null);
+CREATE TABLE notes (id integer primary key, guid text not null, mid integer not null, mod integer not null, usn integer not null, tags text not null, flds text not null, sfld integer not null, csum integer not null, flags integer not null, data text not null);
+CREATE TABLE cards (id integer primary key, nid integer not null, did integer not null, ord integer not null, mod integer not null, usn integer not null, type integer not null, queue integer not null, due integer not null, ivl integer not null, factor integer not null, reps integer not null, lapses integer not null, left integer not null, odue integer not null, odid integer not null, flags integer not null, data text not null);
+CREATE TABLE revlog (id integer primary key, cid integer not null, usn integer not null, ease integer not null, ivl integer not null, lastIvl integer not null, factor integer not null, time integer not null, type integer not null);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+";
+
+/// Id from the parts, FNV-1a keeps it the same across builds so repeated imports don't duplicate
+/// the note type, the deck or the notes
+fn id(parts: &[&str]) -> i64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in parts.iter().flat_map(|p| p.bytes().chain([0x1F])) {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    (h >> 24) as i64
+}
+
+fn value(w: &WordStat, c: Column, text: &str) -> String {
+    match c {
+        Column::Word => w.word.clone(),
+        Column::Traditional => w.traditional.clone(),
+        Column::Reading => w.reading.clone(),
+        Column::Meaning => w.meaning.clone(),
+        Column::Sentence => helper::context_in(text, w.first.0, w.first.1).sentence,
+        Column::Hsk => w.hsk.map(|h| h.to_string()).unwrap_or_default(),
+    }
+}
+
+impl Export {
+    pub const DEFAULT_NOTE_TYPE: &'static str = "cnreader";
+    pub const DEFAULT_COLUMNS: [Column; 4] = [Column::Word, Column::Reading, Column::Meaning, Column::Sentence];
+
+    pub fn new(report: &Report, text: &str, conf: Option<&AnkiExport>, note: Option<&AnkiNote>) -> Self {
+        let columns: Vec<Column> = match conf {
+            Some(c) => c.fields.iter()
+                .filter_map(|f| Column::ALL.iter().find(|c| c.as_str().eq_ignore_ascii_case(f)).copied())
+                .collect(),
+            None => vec![],
+        };
+        let columns = if columns.is_empty() { Self::DEFAULT_COLUMNS.to_vec() } else { columns };
+        let rows = report.unknown()
+            .map(|w| columns.iter().map(|c| value(w, *c, text)).collect())
+            .collect();
+        Self {
+            columns,
+            deck: conf.and_then(|c| c.deck.clone()).or_else(|| note.map(|n| n.deck.clone())),
+            note_type: conf.and_then(|c| c.note_type.clone()),
+            existing_type: note.map(|n| n.note_type.clone()),
+            tags: conf.and_then(|c| c.tags.clone()).or_else(|| note.and_then(|n| n.tags.clone())).unwrap_or_default(),
+            rows,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Text file for File > Import, the header lines set up the import dialog
+    pub fn to_tsv(&self) -> String {
+        let mut res = String::from("#separator:tab\n#html:false\n");
+        res.push_str(&format!("#columns:{}\n", self.columns.iter().map(|c| c.as_str()).collect::<Vec<&str>>().join("\t")));
+        if let Some(d) = &self.deck {
+            res.push_str(&format!("#deck:{}\n", d));
+        }
+        if let Some(n) = self.note_type.as_ref().or(self.existing_type.as_ref()) {
+            res.push_str(&format!("#notetype:{}\n", n));
+        }
+        if !self.tags.is_empty() {
+            res.push_str(&format!("#tags:{}\n", self.tags.join(" ")));
+        }
+        for r in &self.rows {
+            let r: Vec<String> = r.iter().map(|v| v.replace(['\t', '\n', '\r'], " ")).collect();
+            res.push_str(&r.join("\t"));
+            res.push('\n');
+        }
+        res
+    }
+
+    /// Deck package with its own note type, one field per column
+    pub fn to_apkg(&self) -> Dupa<Vec<u8>> {
+        let now = Utc::now().timestamp_millis();
+        let path = std::env::temp_dir().join(format!("cnreader-{}.anki2", now));
+        let res = self.write_collection(&path, now).and_then(|c| Self::zip(&c));
+        let _ = fs::remove_file(&path);
+        res
+    }
+
+    fn zip(collection: &[u8]) -> Dupa<Vec<u8>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("collection.anki2", options)?;
+        zip.write_all(collection)?;
+        zip.start_file("media", options)?;
+        zip.write_all(b"{}")?;
+        Ok(zip.finish()?.into_inner())
+    }
+
+    fn write_collection(&self, path: &Path, now: i64) -> Dupa<Vec<u8>> {
+        let mut c = Connection::open(path)?;
+        c.execute_batch(SCHEMA)?;
+
+        let secs = now / 1000;
+        let note_type = self.note_type.clone().unwrap_or(Self::DEFAULT_NOTE_TYPE.to_string());
+        let deck = self.deck.clone().unwrap_or(Self::DEFAULT_NOTE_TYPE.to_string());
+        let names: Vec<&str> = self.columns.iter().map(|c| c.as_str()).collect();
+        let mid = id(&[&[note_type.as_str()], names.as_slice()].concat());
+        let did = id(&[&deck]);
+
+        let flds: Vec<_> = names.iter().enumerate()
+            .map(|(i, n)| json!({"name": n, "ord": i, "sticky": false, "rtl": false, "font": "Arial", "size": 20, "media": []}))
+            .collect();
+        let back = names[1..].iter().map(|n| format!("{{{{{}}}}}", n)).collect::<Vec<String>>().join("<br>");
+        let models = json!({ mid.to_string(): {
+            "id": mid, "name": note_type, "type": 0, "mod": secs, "usn": -1, "sortf": 0, "did": did,
+            "tmpls": [{"name": "Card 1", "ord": 0, "qfmt": format!("{{{{{}}}}}", names[0]),
+                "afmt": format!("{{{{FrontSide}}}}<hr id=answer>{}", back), "did": null, "bqfmt": "", "bafmt": ""}],
+            "flds": flds,
+            "css": ".card { font-family: arial; font-size: 20px; text-align: center; }",
+            "latexPre": "", "latexPost": "", "tags": [], "vers": [], "req": [[0, "any", [0]]],
+        }});
+        let deck_json = |id: i64, name: &str| json!({
+            "id": id, "name": name, "mod": secs, "usn": -1, "desc": "", "dyn": 0, "conf": 1, "collapsed": false,
+            "newToday": [0, 0], "revToday": [0, 0], "lrnToday": [0, 0], "timeToday": [0, 0],
+            "extendNew": 10, "extendRev": 50,
+        });
+        let decks = json!({ "1": deck_json(1, "Default"), did.to_string(): deck_json(did, &deck) });
+        let dconf = json!({ "1": {
+            "id": 1, "name": "Default", "mod": 0, "usn": 0, "maxTaken": 60, "autoplay": true, "timer": 0, "replayq": true, "dyn": false,
+            "new": {"bury": false, "delays": [1.0, 10.0], "initialFactor": 2500, "ints": [1, 4, 0], "order": 1, "perDay": 20},
+            "lapse": {"delays": [10.0], "leechAction": 1, "leechFails": 8, "minInt": 1, "mult": 0.0},
+            "rev": {"bury": false, "ease4": 1.3, "ivlFct": 1.0, "maxIvl": 36500, "perDay": 200, "hardFactor": 1.2},
+        }});
+        let conf = json!({ "curDeck": did, "curModel": mid, "nextPos": self.rows.len() + 1 });
+
+        let t = c.transaction()?;
+        t.execute("INSERT INTO col VALUES (1, ?1, ?2, ?3, 11, 0, 0, 0, ?4, ?5, ?6, ?7, '{}')",
+            params![secs, secs, now, conf.to_string(), models.to_string(), decks.to_string(), dconf.to_string()])?;
+        let tags = if self.tags.is_empty() { String::new() } else { format!(" {} ", self.tags.join(" ")) };
+        for (i, r) in self.rows.iter().enumerate() {
+            let nid = now + i as i64;
+            let guid = id(&[&note_type, &r[0]]);
+            t.execute("INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, 0, 0, '')",
+                params![nid, format!("{:x}", guid), mid, secs, tags, r.join("\u{1F}"), r[0]])?;
+            t.execute("INSERT INTO cards VALUES (?1, ?1, ?2, 0, ?3, -1, 0, 0, ?4, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+                params![nid, did, secs, i as i64 + 1])?;
+        }
+        t.commit()?;
+        c.close().map_err(|(_, e)| e)?;
+        Ok(fs::read(path)?)
+    }
+}
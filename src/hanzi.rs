@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tracing::{debug, info};
+
+/// The 214 Kangxi radicals in order
+const RADICALS: &str = "一丨丶丿乙亅二亠人儿入八冂冖冫几凵刀力勹匕匚匸十卜卩厂厶又口囗土士夂夊夕大女子宀寸小尢尸屮山巛工己巾干幺广廴廾弋弓彐彡彳心戈戶手支攴文斗斤方无日曰月木欠止歹殳毋比毛氏气水火爪父爻爿片牙牛犬玄玉瓜瓦甘生用田疋疒癶白皮皿目矛矢石示禸禾穴立竹米糸缶网羊羽老而耒耳聿肉臣自至臼舌舛舟艮色艸虍虫血行衣襾見角言谷豆豕豸貝赤走足身車辛辰辵邑酉釆里金長門阜隶隹雨靑非面革韋韭音頁風飛食首香馬骨高髟鬥鬯鬲鬼魚鳥鹵鹿麥麻黃黍黑黹黽鼎鼓鼠鼻齊齒龍龜龠";
+
+/// Simplified forms of radicals, written with an apostrophe in Unihan (120' = 纟)
+const SIMPLIFIED_RADICALS: [(u32, char); 23] = [
+    (120, '纟'), (147, '见'), (149, '讠'), (154, '贝'), (159, '车'), (167, '钅'),
+    (169, '门'), (178, '韦'), (181, '页'), (182, '风'), (183, '飞'), (184, '饣'),
+    (187, '马'), (195, '鱼'), (196, '鸟'), (197, '卤'), (199, '麦'), (201, '黄'),
+    (205, '黾'), (210, '齐'), (211, '齿'), (212, '龙'), (213, '龟'),
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct CharInfo {
+    pub chr: char,
+    /// Kangxi radical number and its form
+    pub radical: Option<(u32, char)>,
+    pub strokes: Option<u32>,
+    /// Ideographic description sequence, e.g. ⿱宀子
+    pub ids: Option<String>,
+}
+
+impl CharInfo {
+    /// Components named in the description, without the structure characters
+    pub fn components(&self) -> Vec<char> {
+        self.ids.as_deref()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !('\u{2FF0}'..='\u{2FFF}').contains(c) && *c != self.chr)
+            .collect()
+    }
+}
+
+impl fmt::Display for CharInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.chr)?;
+        if let Some((n, r)) = self.radical {
+            write!(f, "  radical {} ({})", r, n)?;
+        }
+        if let Some(s) = self.strokes {
+            write!(f, "  {} strokes", s)?;
+        }
+        let comp = self.components();
+        if let Some(ids) = &self.ids {
+            if !comp.is_empty() {
+                write!(f, "\n{} = {}", ids, comp.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(" + "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Character data read from `ids.txt` (cjkvi-ids / CHISE format) and
+/// `Unihan_IRGSources.txt` (kRSUnicode, kTotalStrokes) found next to the dictionary
+pub struct Chars {
+    data: HashMap<char, CharInfo>,
+}
+
+impl Chars {
+    pub const IDS: &'static str = "ids.txt";
+    pub const UNIHAN: &'static str = "Unihan_IRGSources.txt";
+
+    pub fn new(dir: &Path) -> Self {
+        let mut data: HashMap<char, CharInfo> = HashMap::new();
+
+        if let Ok(text) = fs::read_to_string(dir.join(Self::IDS)) {
+            for l in text.lines().filter(|l| l.starts_with("U+")) {
+                let mut it = l.split('\t');
+                if let (Some(_), Some(c), Some(ids)) = (it.next(), it.next(), it.next()) {
+                    let Some(c) = c.chars().next() else { continue };
+                    let ids = ids.split('[').next().unwrap_or_default().to_string();
+                    data.entry(c).or_insert_with(|| CharInfo { chr: c, ..Default::default() }).ids = Some(ids);
+                }
+            }
+        }
+
+        if let Ok(text) = fs::read_to_string(dir.join(Self::UNIHAN)) {
+            for l in text.lines().filter(|l| l.starts_with("U+")) {
+                let mut it = l.split('\t');
+                let (Some(cp), Some(key), Some(val)) = (it.next(), it.next(), it.next()) else { continue };
+                let Some(c) = u32::from_str_radix(&cp[2..], 16).ok().and_then(char::from_u32) else { continue };
+                let first = val.split(' ').next().unwrap_or_default();
+                match key {
+                    "kRSUnicode" => {
+                        let rad = first.split('.').next().unwrap_or_default();
+                        let simplified = rad.ends_with('\'');
+                        if let Ok(n) = rad.trim_end_matches('\'').parse::<u32>() {
+                            let form = SIMPLIFIED_RADICALS.iter()
+                                .find(|r| simplified && r.0 == n)
+                                .map(|r| r.1)
+                                .or_else(|| RADICALS.chars().nth((n as usize).checked_sub(1)?));
+                            if let Some(form) = form {
+                                data.entry(c).or_insert_with(|| CharInfo { chr: c, ..Default::default() }).radical = Some((n, form));
+                            }
+                        }
+                    }
+                    "kTotalStrokes" => {
+                        if let Ok(n) = first.parse() {
+                            data.entry(c).or_insert_with(|| CharInfo { chr: c, ..Default::default() }).strokes = Some(n);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if data.is_empty() {
+            info!("No character data in {}", dir.display());
+        } else {
+            debug!("Character data: {}", data.len());
+        }
+        Self { data }
+    }
+
+    pub fn get(&self, c: char) -> Option<&CharInfo> {
+        self.data.get(&c)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
@@ -3,35 +3,130 @@ use wl_clipboard_rs::paste::{get_contents, ClipboardType, MimeType, Seat};
 #[cfg(target_family="windows")]
 use clipboard_win::{formats, get_clipboard};
 use std::io::Read;
-use std::fmt;
-use crate::chat::ChatPrompt;
-
-#[derive(Clone, Debug, PartialEq)]
-pub enum ChatQuestions {
-    MeaningWord,
-    Example,
-    Synonyms,
+use crate::config::PromptTemplate;
+
+/// Prompts used when the configuration has none
+pub fn default_prompts() -> Vec<PromptTemplate> {
+    [
+        ("Meaning", "Explain the meaning and the usage of the word {word} in this sentence: {sentence}"),
+        ("Examples", "Give some example sentences using the word {word} with the same meaning as in: {sentence}"),
+        ("Synonyms", "“{word}”在这个句子里的同义词是什么？句子：{sentence}"),
+    ]
+    .map(|(name, template)| PromptTemplate { name: name.to_string(), template: template.to_string() })
+    .to_vec()
 }
 
-impl fmt::Display for ChatQuestions {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        f.write_str(match self {
-            Self::MeaningWord => "Explain the meaning and the usage of the word: ",
-            Self::Example => "Give some example sentences using the word: ",
-            Self::Synonyms => "这个词的同义词是什么？:",
-        })
+/// Values of the placeholders in prompt templates
+#[derive(Clone, Debug, Default)]
+pub struct PromptVars {
+    pub word: String,
+    pub sentence: String,
+    pub paragraph: String,
+    pub pinyin: String,
+    pub definition: String,
+    pub text: String,
+}
+
+impl PromptVars {
+    pub const WORD_PLACEHOLDERS: [&'static str; 5] = ["{word}", "{sentence}", "{paragraph}", "{pinyin}", "{definition}"];
+
+    /// Unknown placeholders are left as they are
+    pub fn render(&self, template: &str) -> String {
+        template
+            .replace("{word}", &self.word)
+            .replace("{sentence}", &self.sentence)
+            .replace("{paragraph}", &self.paragraph)
+            .replace("{pinyin}", &self.pinyin)
+            .replace("{definition}", &self.definition)
+            .replace("{text}", &self.text)
     }
 }
 
-impl ChatQuestions {
-    pub fn to_prompt(&self, ai: &str, w: &str) -> ChatPrompt {
-        ChatPrompt {
-            chat_name: ai.to_string(),
-            prompt: format!("{} {}", self, w),
+/// Templates about a word need a selection
+pub fn needs_selection(template: &str) -> bool {
+    PromptVars::WORD_PLACEHOLDERS.iter().any(|p| template.contains(p))
+}
+
+/// Where a looked up word came from in the text pane
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    pub sentence: String,
+    pub paragraph: String,
+}
+
+const SENTENCE_END: &str = "。！？；!?;…";
+/// Closing quotes and brackets after the end of a sentence belong to it
+const CLOSING: &str = "”’」』）)》\"'";
+
+fn ends_sentence(l: &str) -> bool {
+    l.trim_end()
+        .trim_end_matches(|c| CLOSING.contains(c))
+        .ends_with(|c| SENTENCE_END.contains(c))
+}
+
+/// Sentence and paragraph around the character `col` of `line`
+pub fn context_at(line: &str, col: usize) -> Context {
+    let chars: Vec<char> = line.chars().collect();
+    let col = col.min(chars.len());
+    let is_end = |c: &char| SENTENCE_END.contains(*c);
+    let is_closing = |c: &char| CLOSING.contains(*c);
+    let mut start = 0;
+    if let Some(p) = chars[..col].iter().rposition(is_end) {
+        start = p + 1;
+        while start < col && is_closing(&chars[start]) {
+            start += 1;
         }
     }
+    let mut end = chars[col..].iter().position(is_end).map_or(chars.len(), |p| col + p);
+    while end < chars.len() && (is_end(&chars[end]) || is_closing(&chars[end])) {
+        end += 1;
+    }
+    Context {
+        sentence: chars[start..end].iter().collect::<String>().trim().to_string(),
+        paragraph: line.trim().to_string(),
+    }
+}
+
+/// Context of the character `col` of line `ln` in `text`. A line which doesn't
+/// end a sentence continues on the next one, as in hard wrapped or OCR text.
+/// Paragraphs end at blank lines, in a text without any each line is one.
+pub fn context_in(text: &str, ln: usize, col: usize) -> Context {
+    let lines: Vec<&str> = text.lines().collect();
+    if ln >= lines.len() {
+        return Context::default();
+    }
+    let continues = |l: &str| !l.trim().is_empty() && !ends_sentence(l);
+    let mut first = ln;
+    while first > 0 && continues(lines[first - 1]) && !lines[first].trim().is_empty() {
+        first -= 1;
+    }
+    let mut last = ln;
+    while last + 1 < lines.len() && continues(lines[last]) && !lines[last + 1].trim().is_empty() {
+        last += 1;
+    }
+    let offset: usize = lines[first..ln].iter().map(|l| l.chars().count()).sum();
+    let mut res = context_at(&lines[first..=last].concat(), offset + col);
+    let blank = |l: &&str| l.trim().is_empty();
+    if !blank(&lines[ln]) && lines.iter().any(blank) {
+        let start = lines[..ln].iter().rposition(blank).map_or(0, |p| p + 1);
+        let end = lines[ln..].iter().position(blank).map_or(lines.len(), |p| ln + p);
+        res.paragraph = lines[start..end].iter().map(|l| l.trim()).collect();
+    }
+    res
 }
 
+/// Character column of the byte offset `col` of `line`, the editor reports cursors in bytes
+pub fn char_col(line: &str, col: usize) -> usize {
+    line.char_indices().take_while(|(b, _)| *b < col).count()
+}
+
+/// Character column of the occurrence of `sel` in `line` closest to `col`
+pub fn locate(line: &str, sel: &str, col: usize) -> Option<usize> {
+    let n = sel.chars().count();
+    line.match_indices(sel)
+        .map(|(b, _)| line[..b].chars().count())
+        .min_by_key(|c| c.abs_diff(col).min((c + n).abs_diff(col)))
+}
 
 //--------------- Enums -------------
 
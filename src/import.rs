@@ -0,0 +1,92 @@
+use rusqlite::{Connection, params};
+use std::collections::HashMap;
+use std::fs;
+use tracing::{debug, info};
+use crate::Dupa;
+
+/// The table read by `Cedict::new`, columns in the order `Entry::from_row` expects
+const SCHEMA: &str = "CREATE TABLE Cedict (
+    simplified TEXT NOT NULL,
+    traditional TEXT NOT NULL,
+    pinyin TEXT NOT NULL,
+    meaning TEXT NOT NULL,
+    hsk INTEGER
+)";
+
+/// One line of a CC-CEDICT release
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line<'a> {
+    pub tra: &'a str,
+    pub sim: &'a str,
+    pub pin: &'a str,
+    pub mea: &'a str,
+}
+
+/// Parse `傳統 传统 [chuan2 tong3] /tradition/traditional/`
+pub fn parse_line(l: &str) -> Option<Line<'_>> {
+    let l = l.trim();
+    if l.is_empty() || l.starts_with('#') {
+        return None;
+    }
+    let (tra, rest) = l.split_once(' ')?;
+    let (sim, rest) = rest.split_once(' ')?;
+    let (pin, rest) = rest.strip_prefix('[')?.split_once(']')?;
+    let mea = rest.trim().strip_prefix('/')?.strip_suffix('/')?;
+    Some(Line { tra, sim, pin, mea })
+}
+
+/// HSK list, one `word level` pair per line separated by a tab, comma or space
+fn read_hsk(fname: &str) -> Dupa<HashMap<String, u32>> {
+    let mut res = HashMap::new();
+    for l in fs::read_to_string(fname)?.lines() {
+        let mut it = l.split(['\t', ',', ' ']).filter(|s| !s.is_empty());
+        if let (Some(w), Some(lv)) = (it.next(), it.next()) {
+            if let Ok(lv) = lv.trim().parse() {
+                res.insert(w.trim_start_matches('\u{FEFF}').to_string(), lv);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// HSK levels already stored in the database, kept across updates
+fn existing_levels(conn: &Connection) -> HashMap<String, u32> {
+    let mut res = HashMap::new();
+    if let Ok(mut st) = conn.prepare("SELECT * FROM Cedict") {
+        if let Ok(mut rows) = st.query([]) {
+            while let Ok(Some(r)) = rows.next() {
+                if let (Ok(w), Ok(Some(lv))) = (r.get::<_, String>(0), r.get::<_, Option<u32>>(4)) {
+                    res.insert(w, lv);
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Create or replace the Cedict table from a `cedict_ts.u8` file, returns the number of entries
+pub fn import(src: &str, db: &str, hsk: Option<&str>) -> Dupa<usize> {
+    let text = fs::read_to_string(src)?;
+    let mut conn = Connection::open(db)?;
+
+    let mut levels = existing_levels(&conn);
+    debug!("Kept {} HSK levels", levels.len());
+    if let Some(hsk) = hsk {
+        levels.extend(read_hsk(hsk)?);
+    }
+
+    let tx = conn.transaction()?;
+    tx.execute("DROP TABLE IF EXISTS Cedict", [])?;
+    tx.execute(SCHEMA, [])?;
+    let mut n = 0;
+    {
+        let mut st = tx.prepare("INSERT INTO Cedict VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for l in text.lines().filter_map(parse_line) {
+            st.execute(params![l.sim, l.tra, l.pin, l.mea, levels.get(l.sim)])?;
+            n += 1;
+        }
+    }
+    tx.commit()?;
+    info!("Imported {} entries into {}", n, db);
+    Ok(n)
+}
@@ -5,10 +5,10 @@ use once_cell::sync::{Lazy, OnceCell};
 use std::fs;
 use tracing::{debug, error, info};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use iced::widget::{button, column, row, text_editor, Button, scrollable,
-text, container, combo_box, ComboBox};
+text, container, combo_box, ComboBox, text_input};
 use iced::{Element, Subscription};
-use helper::ChatQuestions;
 use std::path::Path;
 
 mod config;
@@ -16,16 +16,29 @@ mod anki;
 mod chat;
 mod helper;
 mod cedict;
+mod segment;
+mod import;
+mod pinyin;
+mod convert;
+mod bench;
+mod hanzi;
+mod card;
+mod marks;
+mod report;
+mod export;
+mod conversation;
+mod sse;
+mod provider;
+#[cfg(test)]
+mod mock;
 
 type Dupa<T> = Result<T, Box<dyn std::error::Error>>;
 
-make_enum!(AiChat, [ChatGPT, Deepseek, Grok, Ollama]);
-
 static RESULT: Lazy<Mutex<scrollable::Id>> = Lazy::new(|| {
     Mutex::new(scrollable::Id::unique())
 });
 
-static RECV: OnceCell<async_channel::Receiver<chat::ChatPrompt>> = OnceCell::new();
+static RECV: OnceCell<async_channel::Receiver<chat::ChatRequest>> = OnceCell::new();
 static CONFIG: OnceCell<config::Config> = OnceCell::new();
 
 // Message
@@ -38,7 +51,7 @@ enum Message {
     AppendText(String),
     SetTextWithCursor(String,i32),
     ChatStreamEvent(chat::Event),
-    AskChat(ChatQuestions),
+    AskChat(usize),
     AiSelected(String),
     ShowError(Arc<String>),
     Void,
@@ -47,6 +60,27 @@ enum Message {
     HideModal,
     Deepl,
     ToSimplified,
+    ToTraditional,
+    SearchInput(String),
+    Search,
+    Lookup(String),
+    AddToAnki(usize),
+    AnkiAdded(String),
+    ToggleMarks,
+    RefreshMarks,
+    Report,
+    ExportReport,
+    ExportWords,
+    CheckAnki,
+    ChatInput(String),
+    ChatSend,
+    ChatClear,
+    ChatStop,
+    ChatBranch,
+    ChatSwitch(usize),
+    ToggleReasoning,
+    /// The collections, why some of them couldn't be read, and their modification time
+    AnkiLoaded(Result<(Arc<anki::Anki>, Vec<String>, Option<SystemTime>), String>),
     ShowAnki,
     Play,
     SaveFile,
@@ -54,21 +88,40 @@ enum Message {
 }
 
 struct Reader {
-    chat_sx: async_channel::Sender<chat::ChatPrompt>,
+    chat_sx: async_channel::Sender<chat::ChatRequest>,
+    /// Id of the last chat request
+    chat_id: u64,
+    conversation: conversation::Conversation,
+    chat_input: String,
+    prompts: Vec<config::PromptTemplate>,
+    show_reasoning: bool,
 
     text: text_editor::Content,
     result: text_editor::Content,
+    cards: Vec<card::Card>,
+    info: Option<String>,
+    context: Option<helper::Context>,
     cedict: cedict::Cedict,
+    chars: hanzi::Chars,
 
     show_anki: bool,
     show_modal: bool,
 
     modal_text: String,
+    search: String,
+    marks: marks::Marks,
+    /// Time of the last edit the marks don't cover yet
+    marks_dirty: Option<Instant>,
+    report: Option<report::Report>,
 
     ai_states: combo_box::State<String>,
     ai: Option<String>,
 
-    anki: anki::Anki,
+    anki: Arc<anki::Anki>,
+    /// Why the collection couldn't be read
+    anki_error: Option<String>,
+    anki_mtime: Option<SystemTime>,
+    anki_loading: bool,
 }
 
 pub fn run(theme: &str) -> Result<(), iced::Error> {
@@ -91,6 +144,13 @@ pub fn run(theme: &str) -> Result<(), iced::Error> {
 impl Reader {
     const FNAME: &'static str = "dict.db";
     const SAVE: &'static str = "save";
+    /// Seconds between checks for changes in the Anki collection
+    const ANKI_RELOAD: u64 = 30;
+    /// Typing pause after which the marks are brought up to date
+    const MARKS_DELAY: Duration = Duration::from_millis(400);
+    /// Longest selection looked up, in characters, for Chinese and for English or pinyin
+    const MAX_SELECTION: usize = 5;
+    const MAX_LATIN_SELECTION: usize = 40;
 
     pub fn new() -> Self {
         let (chat_sx, chat_rx) = async_channel::unbounded();
@@ -98,7 +158,15 @@ impl Reader {
         let rr = RECV.set(chat_rx);
 
         debug!("Anki init");
-        let anki = anki::Anki::new(&*shellexpand::tilde(CONFIG.get().unwrap().anki.as_str()));
+        let conf = CONFIG.get().unwrap();
+        let anki_paths = conf.anki.paths();
+        let anki_mtime = anki::Anki::modified(&anki_paths);
+        let (anki, errors) = anki::Anki::load_all(&anki_paths, &conf.anki_fields.clone().unwrap_or_default(), &conf.anki_decks.clone().unwrap_or_default());
+        if anki.is_none() {
+            info!("Anki disabled");
+        }
+        let anki_error = (!errors.is_empty()).then(|| errors.join("; "));
+        let anki_mtime = anki_mtime.filter(|_| errors.is_empty());
 
         debug!("Anki done");
         match rr {
@@ -110,6 +178,10 @@ impl Reader {
         debug!("Self-init");
 
         let cedict = cedict::Cedict::new(Self::FNAME).expect("Failed to load the dictionary");
+        let dict_dir = Path::new(Self::FNAME).parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let chars = hanzi::Chars::new(dict_dir);
         let ai_chats = CONFIG.get().unwrap()
             .ai_chats
             .clone()
@@ -119,18 +191,34 @@ impl Reader {
         Self {
             text: text_editor::Content::new(),
             result: text_editor::Content::new(),
+            cards: vec![],
+            info: None,
+            context: None,
             chat_sx,
+            chat_id: 0,
+            conversation: conversation::Conversation::default(),
+            chat_input: String::new(),
+            show_reasoning: false,
+            prompts: conf.prompts.clone().unwrap_or_else(helper::default_prompts),
 
             cedict,
+            chars,
             show_modal: false,
             show_anki: false,
 
             modal_text: String::new(),
+            search: String::new(),
+            report: None,
+            marks: marks::Marks::empty(conf.window.marks.clone().map(marks::MarkMode::from).unwrap_or_default()),
+            marks_dirty: None,
 
             ai: None,
             ai_states: combo_box::State::new(ai_chats),
 
-            anki,
+            anki: Arc::new(anki),
+            anki_error,
+            anki_mtime,
+            anki_loading: false,
         }
     }
 
@@ -139,12 +227,146 @@ impl Reader {
         self.show_modal = true;
     }
 
+    /// Colour the words of the text by their state in Anki
+    fn update_marks(&mut self) {
+        let known_interval = CONFIG.get().unwrap().known_interval.unwrap_or(anki::Anki::KNOWN_INTERVAL);
+        let version = self.marks.version() + 1;
+        self.marks_dirty = None;
+        self.marks = marks::Marks::new(&self.cedict, &self.anki, &self.text.text(), self.marks.mode, known_interval, version);
+    }
+
+    /// Send `question` to the selected chat, continuing the conversation
+    fn ask(&mut self, question: &str) -> iced::Task<Message> {
+        let Some(ai) = self.ai.clone() else {
+            return iced::Task::none();
+        };
+        if self.conversation.is_empty() {
+            let conf = CONFIG.get().unwrap();
+            let system = conf.ai_chats.get(&ai).and_then(|c| c.system.as_deref())
+                .or(conf.chat_system.as_deref())
+                .unwrap_or(conversation::Conversation::DEFAULT_SYSTEM);
+            self.conversation = conversation::Conversation::new(system, &self.text.text());
+        }
+        self.chat_id += 1;
+        let messages = self.conversation.ask(question, self.chat_id);
+        debug!("Prompt: {}", question);
+        self.show_conversation();
+        self.send_chat(chat::ChatRequest::Prompt(chat::ChatPrompt { id: self.chat_id, chat_name: ai, messages }))
+    }
+
+    fn send_chat(&self, r: chat::ChatRequest) -> iced::Task<Message> {
+        let chat_sx = self.chat_sx.clone();
+        iced::Task::perform(async move {
+            report_err!( chat_sx.send(r).await );
+        },
+        |_e| {
+            Message::Void
+        })
+    }
+
+    /// Placeholder values for the selected `word`
+    fn prompt_vars(&self, word: &str) -> helper::PromptVars {
+        let entries = if word.is_empty() { vec![] } else { self.cedict.lookup(word) };
+        let context = self.context_at_cursor().unwrap_or_default();
+        helper::PromptVars {
+            word: word.to_string(),
+            sentence: context.sentence,
+            paragraph: context.paragraph,
+            pinyin: entries.iter()
+                .map(|m| pinyin::render(m.0.pin(), pinyin::style()))
+                .collect::<Vec<String>>()
+                .join(", "),
+            definition: entries.iter()
+                .map(|m| m.0.mea().split('/').filter(|s| !s.is_empty()).collect::<Vec<&str>>().join("; "))
+                .collect::<Vec<String>>()
+                .join(" | "),
+            text: self.text.text(),
+        }
+    }
+
+    fn show_conversation(&mut self) {
+        self.clear_result();
+        self.result = text_editor::Content::with_text(&self.conversation.transcript());
+        self.result.perform( text_editor::Action::Move( text_editor::Motion::DocumentEnd ) );
+    }
+
+    /// Empty the result pane, both the text and the dictionary cards
+    fn clear_result(&mut self) {
+        self.result = text_editor::Content::with_text("");
+        self.cards.clear();
+        self.info = None;
+    }
+
+    fn show_entries(&mut self, s: &str) {
+        self.cards = if is_latin(s) {
+            self.cedict.search_latin(s).iter().map(|e| card::Card::new(e, cedict::Form::Both, &self.anki)).collect()
+        } else {
+            self.cedict.lookup(s).iter().map(|m| card::Card::new(m.0, m.1, &self.anki)).collect()
+        };
+        self.info = self.char_info(s);
+    }
+
+    /// Radical, strokes, components and common words of a single character
+    fn char_info(&self, s: &str) -> Option<String> {
+        let mut it = s.chars();
+        let c = it.next().filter(|c| segment::is_han(*c) && it.next().is_none())?;
+        let mut res = match self.chars.get(c) {
+            Some(info) => format!("{}\n", info),
+            None => String::new(),
+        };
+        let mut words: Vec<&cedict::Entry> = self.cedict.search(s)
+            .into_iter()
+            .filter(|e| e.len() > 1)
+            .collect();
+        words.sort_by_key(|e| (e.hsk().unwrap_or(u32::MAX), e.len()));
+        if !words.is_empty() {
+            res.push_str(&format!("Words with {}:\n", c));
+            for e in words.iter().take(20) {
+                res.push_str(&format!("- {}\n", e.brief()));
+            }
+        }
+        Some(res)
+    }
+
+    /// Context of the selection, or of the cursor when nothing is selected
+    fn context_at_cursor(&self) -> Option<helper::Context> {
+        let (line, col) = self.text.cursor_position();
+        let text = self.text.text();
+        let l = text.lines().nth(line)?;
+        let col = helper::char_col(l, col);
+        let col = self.text.selection()
+            .and_then(|s| helper::locate(l, s.trim(), col))
+            .unwrap_or(col);
+        Some(helper::context_in(&text, line, col))
+    }
+
+    /// Look up the dictionary word under the cursor
+    fn lookup_at_cursor(&mut self) {
+        let (line, col) = self.text.cursor_position();
+        let text = self.text.text();
+        let w = text.lines().nth(line)
+            .and_then(|l| segment::word_under_cursor(&self.cedict, l, col))
+            .filter(|w| w.known);
+        if let Some(w) = w {
+            debug!("Word at {}:{} -> {}", line, col, w.word);
+            self.show_entries(w.word.as_str());
+        }
+    }
+
     fn title(&self) -> String {
         "Chinese Reader".to_string()
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        Subscription::run(chat::connect).map(Message::ChatStreamEvent)
+        let mut subs = vec![Subscription::run(chat::connect).map(Message::ChatStreamEvent)];
+        match CONFIG.get().unwrap().anki_reload.unwrap_or(Self::ANKI_RELOAD) {
+            0 => (),
+            s => subs.push(iced::time::every(Duration::from_secs(s)).map(|_| Message::CheckAnki)),
+        }
+        if self.marks_dirty.is_some() {
+            subs.push(iced::time::every(Self::MARKS_DELAY / 4).map(|_| Message::RefreshMarks));
+        }
+        Subscription::batch(subs)
     }
 
     pub fn view(&self) -> Element<'_, Message> {
@@ -163,6 +385,7 @@ impl Reader {
             .on_action(Message::EditAction)
             .height(h*0.55)
             .size(font_size)
+            .highlight_with::<marks::Marker>(self.marks.clone(), marks::format)
             .into();
 
         let is_sel = self.text.selection().is_some();
@@ -181,22 +404,41 @@ impl Reader {
             button("Deepl")
         };
 
-        let idc_result: Element<'_, Message> = text_editor( &self.result )
-            .placeholder("")
-            .on_action(Message::ResultAction)
-            .height(h*0.3)
-            .size(font_size-3.0)
-            .into();
+        let idc_result: Element<'_, Message> = if self.cards.is_empty() && self.info.is_none() {
+            text_editor( &self.result )
+                .placeholder("")
+                .on_action(Message::ResultAction)
+                .height(h*0.3)
+                .size(font_size-3.0)
+                .into()
+        } else {
+            let can_add = CONFIG.get().unwrap().anki_note.is_some();
+            scrollable(card::view(&self.cards, self.info.as_deref(), can_add, font_size-3.0))
+                .height(h*0.3)
+                .into()
+        };
 
         let idc_ai: ComboBox<String, Message> = combo_box(&self.ai_states, "", self.ai.as_ref(), Message::AiSelected).width(140.0);
 
-        let idc_meaning: Button<Message> = if is_sel { button("Meaning").on_press(Message::AskChat(helper::ChatQuestions::MeaningWord)) } else { button("Meaning") }.width(but_w);
-        let idc_examples: Button<Message> = if is_sel { button("Examples").on_press(Message::AskChat(helper::ChatQuestions::Example)) } else { button("Examples") }.width(but_w);
-        let idc_synonyms: Button<Message> = if is_sel { button("Synonyms").on_press(Message::AskChat(helper::ChatQuestions::Synonyms)) } else { button("Synonyms") }.width(but_w);
+        let can_ask = self.ai.is_some();
+        let idc_prompts = self.prompts.iter().enumerate().map(|(i, p)| {
+            let b: Button<Message> = if can_ask && (is_sel || !helper::needs_selection(&p.template)) {
+                button(text(&p.name)).on_press(Message::AskChat(i))
+            } else {
+                button(text(&p.name))
+            };
+            b.width(but_w).into()
+        });
+
+        let idc_search = text_input("Search", &self.search)
+            .on_input(Message::SearchInput)
+            .on_submit(Message::Search)
+            .width(200.0);
 
         let idc_sim: Button<Message> = button("Simplified").width(100.0).on_press(Message::ToSimplified);
-        let idc_anki: Button<Message> = match (&self.anki, is_sel) {
-            (anki::Anki::AnkiDb{..},true) => button("Anki").width(but_w).on_press(Message::ShowAnki),
+        let idc_tra: Button<Message> = button("Traditional").width(100.0).on_press(Message::ToTraditional);
+        let idc_anki: Button<Message> = match (self.anki.is_db(), is_sel) {
+            (true,true) => button("Anki").width(but_w).on_press(Message::ShowAnki),
             _ => button("Anki").width(but_w),
         }.width(55.0);
 
@@ -214,22 +456,79 @@ impl Reader {
 
         let idc_save: Button<Message> = button("Save").on_press(Message::SaveFile);
 
-        let up_buttons = row![ idc_deepl, idc_anki, idc_el, idc_sim ]
+        let idc_report: Button<Message> = button("Report").on_press(Message::Report);
+        let idc_export: Button<Message> = if self.report.is_some() {
+            button("Export CSV").on_press(Message::ExportReport)
+        } else {
+            button("Export CSV")
+        };
+
+        let idc_marks: Button<Message> = button(text(format!("Marks: {}", self.marks.mode))).on_press(Message::ToggleMarks);
+
+        let idc_export_words: Button<Message> = button("Export words").on_press(Message::ExportWords);
+
+        let up_buttons = row![ idc_deepl, idc_anki, idc_el, idc_sim, idc_tra, idc_search, idc_marks ]
             .height(h * 0.05)
             .spacing(5)
             .align_y(iced::Alignment::Center);
 
-        let buttons = row![idc_ocr, idc_ocr_file, idc_ai, idc_meaning, idc_examples, idc_synonyms, idc_read, idc_save]
+        let buttons = row![idc_ocr, idc_ocr_file, idc_ai]
+            .extend(idc_prompts)
+            .extend([idc_read.into(), idc_save.into(), idc_report.into(), idc_export.into(), idc_export_words.into()])
             .height(h * 0.05)
             .spacing(5)
             .align_y(iced::Alignment::Center);
 
-        let controls = column![
+        let mut idc_status = row![self.marks.legend(font_size-4.0)].spacing(20);
+        if let Some(e) = &self.anki_error {
+            idc_status = idc_status.push(text(format!("Anki: {}", e)).size(font_size-4.0).style(text::danger));
+        }
+
+        let idc_chat_input = text_input("Ask the chat", &self.chat_input)
+            .on_input(Message::ChatInput)
+            .on_submit(Message::ChatSend);
+        let (cur, n) = self.conversation.position();
+        let idc_prev: Button<Message> = if cur > 0 { button("<").on_press(Message::ChatSwitch(cur - 1)) } else { button("<") };
+        let idc_next: Button<Message> = if cur + 1 < n { button(">").on_press(Message::ChatSwitch(cur + 1)) } else { button(">") };
+        let idc_branch: Button<Message> = if !self.conversation.turns().is_empty() && !self.conversation.is_streaming() {
+            button("Branch").on_press(Message::ChatBranch)
+        } else {
+            button("Branch")
+        };
+        let idc_clear: Button<Message> = if !self.conversation.is_empty() {
+            button("Clear").on_press(Message::ChatClear)
+        } else {
+            button("Clear")
+        };
+        let idc_stop: Button<Message> = if self.conversation.is_streaming() {
+            button("Stop").on_press(Message::ChatStop)
+        } else {
+            button("Stop")
+        };
+
+        let reasoning = self.conversation.reasoning().filter(|_| self.cards.is_empty() && self.info.is_none());
+        let idc_reasoning: Button<Message> = match (reasoning, self.show_reasoning) {
+            (Some(_), true) => button("Hide reasoning").on_press(Message::ToggleReasoning),
+            (Some(_), false) => button("Show reasoning").on_press(Message::ToggleReasoning),
+            (None, _) => button("Show reasoning"),
+        };
+
+        let chat_buttons = row![idc_chat_input, idc_prev, text(format!("{}/{}", cur + 1, n.max(1))), idc_next, idc_branch, idc_clear, idc_stop, idc_reasoning]
+            .height(h * 0.05)
+            .spacing(5)
+            .align_y(iced::Alignment::Center);
+
+        let mut controls = column![
             idc_text,
+            idc_status,
             up_buttons,
-            idc_result, 
-            buttons
             ].align_x(iced::Alignment::Center);
+        if let Some(r) = reasoning.filter(|_| self.show_reasoning) {
+            controls = controls.push(scrollable(text(r).size(font_size-5.0).style(text::secondary))
+                .height(h*0.15)
+                .width(iced::Length::Fill));
+        }
+        let controls = controls.push(idc_result).push(chat_buttons).push(buttons);
 
         if self.show_modal {
             let alert = container(
@@ -252,19 +551,30 @@ impl Reader {
                 match a {
                     text_editor::Action::Select(_) | text_editor::Action::Drag(_) => {
                         self.text.perform(a);
-                        self.result = text_editor::Content::with_text("");
+                        self.clear_result();
                         if let Some(s) = self.text.selection() {
-                            if s.len() > 15 {
+                            let max = if is_latin(s.as_str()) { Self::MAX_LATIN_SELECTION } else { Self::MAX_SELECTION };
+                            if s.chars().count() > max {
                                 return iced::Task::none();
                             }
-                            let res = self.cedict.find(s.as_str());
-                            res.iter().for_each(|v| {
-                                self.result.perform( text_editor::Action::Edit(text_editor::Edit::Paste( Arc::new( v.to_string() ) ) ) );
-                            });
+                            self.context = self.context_at_cursor();
+                            self.show_entries(s.as_str());
                         }
 
                     },
-                    _ => self.text.perform(a),
+                    text_editor::Action::Click(_) => {
+                        self.text.perform(a);
+                        self.clear_result();
+                        self.context = self.context_at_cursor();
+                        self.lookup_at_cursor();
+                    },
+                    _ => {
+                        let edit = a.is_edit();
+                        self.text.perform(a);
+                        if edit && self.marks.mode != marks::MarkMode::Off {
+                            self.marks_dirty = Some(Instant::now());
+                        }
+                    },
                 }
                 iced::Task::none()
             }
@@ -277,15 +587,19 @@ impl Reader {
                 iced::Task::none()
             }
             Message::AppendResult(r) => {
+                self.cards.clear();
+                self.info = None;
                 self.result.perform( text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new(r)) ) );
                 iced::Task::none()
             }
             Message::AppendText(r) => {
                 self.text.perform( text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new(r)) ) );
+                self.update_marks();
                 iced::Task::none()
             }
             Message::SetTextWithCursor(r,c) => {
                 self.text = text_editor::Content::with_text(r.as_str());
+                self.update_marks();
                 debug!("Trying to move cursor to line {}", c);
                 self.text.perform( text_editor::Action::Scroll { lines: c });
                 for _i in 0..c {
@@ -296,13 +610,25 @@ impl Reader {
 
             Message::ChatStreamEvent(e) => {
                 match e {
-                    chat::Event::MessageReceived(m) => {
-                        self.result.perform( text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new(m)) ) );
+                    chat::Event::MessageReceived(id, m) => {
+                        if self.conversation.receive(id, &m) && self.cards.is_empty() && self.info.is_none() {
+                            self.result.perform( text_editor::Action::Move( text_editor::Motion::DocumentEnd ) );
+                            self.result.perform( text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new(m)) ) );
+                        }
+                    }
+                    chat::Event::ReasoningReceived(id, r) => {
+                        self.conversation.think(id, &r);
                     }
-                    chat::Event::End => {
+                    chat::Event::End(id) => {
                         info!("Stream ended");
+                        self.conversation.end(id);
                     }
-                    chat::Event::Error(e) => {
+                    chat::Event::Cancelled(id) => {
+                        info!("Stream cancelled");
+                        self.conversation.end(id);
+                    }
+                    chat::Event::Error(id, e) => {
+                        self.conversation.end(id);
                         self.display_av(e.as_str());
                     }
                 }
@@ -322,36 +648,245 @@ impl Reader {
                 self.text = text_editor::Content::with_text("");
                 let res = self.cedict.to_sim(s.as_str());
                 self.text.perform( text_editor::Action::Edit( text_editor::Edit::Paste( Arc::new(res) ) ) );
+                self.update_marks();
+                iced::Task::none()
+            }
+            Message::ToTraditional => {
+                let s = self.text.text();
+                self.text = text_editor::Content::with_text("");
+                let res = self.cedict.to_tra(s.as_str());
+                self.text.perform( text_editor::Action::Edit( text_editor::Edit::Paste( Arc::new(res) ) ) );
+                self.update_marks();
+                iced::Task::none()
+            }
+            Message::ToggleMarks => {
+                self.marks.mode = self.marks.mode.next();
+                self.update_marks();
+                iced::Task::none()
+            }
+            Message::RefreshMarks => {
+                if self.marks_dirty.is_some_and(|t| t.elapsed() >= Self::MARKS_DELAY) {
+                    self.update_marks();
+                }
+                iced::Task::none()
+            }
+            Message::Report => {
+                self.clear_result();
+                let r = report::Report::new(&self.cedict, &self.anki, &self.text.text());
+                self.result.perform( text_editor::Action::Edit( text_editor::Edit::Paste( Arc::new(r.to_string()) ) ) );
+                self.report = Some(r);
                 iced::Task::none()
             }
-            Message::AskChat(q) => {
-                self.result = text_editor::Content::with_text("");
-                let w = self.text.selection();
-                if w.is_none() {
+            Message::ExportReport => {
+                let Some(r) = &self.report else {
+                    return iced::Task::none();
+                };
+                let Some(file) = rfd::FileDialog::new()
+                    .add_filter("csv", &["csv"])
+                    .set_file_name("unknown_words.csv")
+                    .save_file() else {
+                    return iced::Task::none();
+                };
+                let csv = r.to_csv();
+                iced::Task::perform(async move {
+                    tokio::fs::write(file, csv.as_bytes()).await
+                }, |r| {
+                    match r {
+                        Ok(_) => Message::Void,
+                        Err(e) => Message::ShowError(Arc::new(e.to_string())),
+                    }
+                })
+            }
+            Message::ExportWords => {
+                let text = self.text.text();
+                let r = report::Report::new(&self.cedict, &self.anki, &text);
+                let conf = CONFIG.get().unwrap();
+                let ex = export::Export::new(&r, &text, conf.anki_export.as_ref(), conf.anki_note.as_ref());
+                if ex.len() == 0 {
+                    self.display_av("No unknown words in the text");
                     return iced::Task::none();
                 }
-                let w = w.unwrap();
-
-                if let Some(ai) = &self.ai {
-                    let prompt = q.to_prompt(&ai, w.as_str());
-                    debug!("Prompt: {}", prompt.prompt);
-                    let chat_sx = self.chat_sx.clone();
-                    return iced::Task::perform(async move {
-                        report_err!( chat_sx.send(prompt).await );
-                    },
-                    |_e| {
-                        Message::Void
-                    });
+                let Some(file) = rfd::FileDialog::new()
+                    .add_filter("Anki text", &["txt"])
+                    .add_filter("Anki package", &["apkg"])
+                    .set_file_name("unknown_words.txt")
+                    .save_file() else {
+                    return iced::Task::none();
+                };
+                let data = if file.extension().is_some_and(|e| e == "apkg") {
+                    match ex.to_apkg() {
+                        Ok(d) => d,
+                        Err(e) => {
+                            self.display_av(&e.to_string());
+                            return iced::Task::none();
+                        }
+                    }
+                } else {
+                    ex.to_tsv().into_bytes()
+                };
+                info!("Exporting {} words to {}", ex.len(), file.display());
+                iced::Task::perform(async move {
+                    tokio::fs::write(file, data).await
+                }, |r| {
+                    match r {
+                        Ok(_) => Message::Void,
+                        Err(e) => Message::ShowError(Arc::new(e.to_string())),
+                    }
+                })
+            }
+            Message::CheckAnki => {
+                let conf = CONFIG.get().unwrap();
+                let paths = conf.anki.paths();
+                let mtime = anki::Anki::modified(&paths);
+                if self.anki_loading || mtime.is_none() || mtime == self.anki_mtime {
+                    return iced::Task::none();
                 }
+                debug!("Anki collection changed, reloading");
+                self.anki_loading = true;
+                let fields = conf.anki_fields.clone().unwrap_or_default();
+                let decks = conf.anki_decks.clone().unwrap_or_default();
+                iced::Task::perform(async move {
+                    tokio::task::spawn_blocking(move || anki::Anki::load_all(&paths, &fields, &decks)).await
+                }, move |r| {
+                    match r {
+                        Ok((a, errors)) => Message::AnkiLoaded(Ok((Arc::new(a), errors, mtime))),
+                        Err(e) => Message::AnkiLoaded(Err(e.to_string())),
+                    }
+                })
+            }
+            Message::AnkiLoaded(r) => {
+                self.anki_loading = false;
+                match r {
+                    Ok((a, errors, mtime)) => {
+                        info!("Anki reloaded");
+                        // A collection that failed, e.g. while Anki was writing it, is read again on the next check
+                        if errors.is_empty() {
+                            self.anki_mtime = mtime;
+                        }
+                        if a.is_db() || errors.is_empty() {
+                            self.anki = a;
+                            self.update_marks();
+                        }
+                        self.anki_error = (!errors.is_empty()).then(|| errors.join("; "));
+                    }
+                    Err(e) => {
+                        error!("Anki reload failed: {}", e);
+                        self.anki_error = Some(e);
+                    }
+                }
+                iced::Task::none()
+            }
+            Message::SearchInput(s) => {
+                self.search = s;
+                iced::Task::none()
+            }
+            Message::Search => {
+                self.clear_result();
+                let s = self.search.trim().to_string();
+                self.context = None;
+                if is_latin(s.as_str()) {
+                    self.show_entries(s.as_str());
+                } else if !s.is_empty() {
+                    let mut res = self.cedict.search(s.as_str());
+                    res.sort_by_key(|e| e.len());
+                    self.cards = res.iter()
+                        .take(cedict::Cedict::MAX_RESULTS)
+                        .map(|e| card::Card::new(e, cedict::Form::Both, &self.anki))
+                        .collect();
+                }
+                iced::Task::none()
+            }
+            Message::Lookup(w) => {
+                self.clear_result();
+                self.context = None;
+                self.show_entries(w.as_str());
+                iced::Task::none()
+            }
+            Message::AddToAnki(i) => {
+                let (Some(c), Some(conf)) = (self.cards.get(i), CONFIG.get().unwrap().anki_note.clone()) else {
+                    return iced::Task::none();
+                };
+                let note = c.to_note(self.context.as_ref());
+                let word = note.word.clone();
+                iced::Task::perform(async move {
+                    anki::add_note(&conf, note).await
+                }, move |r| {
+                    match r {
+                        Ok(id) => {
+                            info!("Added Anki note {}", id);
+                            Message::AnkiAdded(word.clone())
+                        }
+                        Err(e) => Message::ShowError(Arc::new(e.to_string())),
+                    }
+                })
+            }
+            Message::AnkiAdded(w) => {
+                self.cards.iter_mut()
+                    .filter(|c| c.headword == w)
+                    .for_each(|c| c.in_anki = true);
+                iced::Task::none()
+            }
+            Message::AskChat(i) => {
+                let Some(p) = self.prompts.get(i) else {
+                    return iced::Task::none();
+                };
+                let w = self.text.selection().unwrap_or_default();
+                if w.is_empty() && helper::needs_selection(&p.template) {
+                    return iced::Task::none();
+                }
+                let question = self.prompt_vars(w.trim()).render(&p.template);
+                self.clear_result();
+
+                // Buttons start a new conversation, follow-ups go through the chat input
+                self.conversation = conversation::Conversation::default();
+                self.ask(&question)
+            }
+            Message::ChatInput(s) => {
+                self.chat_input = s;
+                iced::Task::none()
+            }
+            Message::ChatSend => {
+                let q = self.chat_input.trim().to_string();
+                if q.is_empty() || self.ai.is_none() {
+                    return iced::Task::none();
+                }
+                self.chat_input.clear();
+                self.ask(&q)
+            }
+            Message::ChatClear => {
+                let streaming = self.conversation.is_streaming();
+                self.conversation = conversation::Conversation::default();
+                self.clear_result();
+                if streaming {
+                    return self.send_chat(chat::ChatRequest::Cancel);
+                }
+                iced::Task::none()
+            }
+            Message::ChatStop => {
+                self.conversation.stop();
+                self.send_chat(chat::ChatRequest::Cancel)
+            }
+            Message::ChatBranch => {
+                self.conversation.branch();
+                self.show_conversation();
+                iced::Task::none()
+            }
+            Message::ChatSwitch(i) => {
+                self.conversation.switch(i);
+                self.show_conversation();
+                iced::Task::none()
+            }
+            Message::ToggleReasoning => {
+                self.show_reasoning = !self.show_reasoning;
                 iced::Task::none()
             }
             Message::ShowAnki => {
                 let s = self.text.selection();
-                self.result = text_editor::Content::new();
+                self.clear_result();
                 match &s {
                     Some(s) => {
                         let r = self.anki.search(s.as_str());
-                        r.iter().for_each(|rl| self.result.perform( text_editor::Action::Edit( text_editor::Edit::Paste( Arc::new(format!("-\t{}{}", rl.trim(), "\n") ))) ));
+                        r.iter().for_each(|rl| self.result.perform( text_editor::Action::Edit( text_editor::Edit::Paste( Arc::new(format!("-\t{}{}", rl, "\n") ))) ));
                     },
                     None => {
                         return iced::Task::none();
@@ -396,7 +931,7 @@ impl Reader {
                 iced::widget::focus_next()
             }
             Message::Deepl => {
-                self.result = text_editor::Content::with_text("");
+                self.clear_result();
                 let s = self.text.selection().unwrap_or( self.text.text() );
                 let sel = Arc::new(s);
                 iced::Task::perform(chat::ask_deepl_a(sel),
@@ -465,12 +1000,45 @@ impl Reader {
     }
 }
 
+/// Text to be searched in the English definitions
+fn is_latin(s: &str) -> bool {
+    s.chars().any(|c| c.is_ascii_alphabetic()) && !s.chars().any(segment::is_han)
+}
+
 impl Default for Reader {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Command line mode, `cnreader import <cedict_ts.u8> [hsk list]` or `cnreader bench`
+fn command(args: &[String]) -> i32 {
+    match args.first().map(String::as_str) {
+        Some("bench") => {
+            match bench::run(Reader::FNAME) {
+                Ok(_) => 0,
+                Err(e) => {
+                    error!("Benchmark failed: {}", e.to_string());
+                    1
+                }
+            }
+        }
+        Some("import") if args.len() > 1 => {
+            match import::import(&args[1], Reader::FNAME, args.get(2).map(String::as_str)) {
+                Ok(_) => 0,
+                Err(e) => {
+                    error!("Import failed: {}", e.to_string());
+                    1
+                }
+            }
+        }
+        _ => {
+            eprintln!("Usage: cnreader import <cedict_ts.u8> [hsk list]\n       cnreader bench");
+            2
+        }
+    }
+}
+
 //#[tokio::main]
 fn main() -> Result<(), iced::Error> {
     #[cfg(debug_assertions)]
@@ -485,6 +1053,11 @@ fn main() -> Result<(), iced::Error> {
         .with_max_level(tracing::Level::INFO)
         .init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(command(&args));
+    }
+
     let t: config::Config = toml::from_str( fs::read_to_string( "./app.toml" ).unwrap().as_str() ).unwrap();
     let theme: String = t.window.theme.clone();
     debug!("Set config");
@@ -0,0 +1,180 @@
+use std::ops::Range;
+use std::sync::Arc;
+use iced::advanced::text::highlighter::{self, Highlighter};
+use iced::widget::{text, Row};
+use iced::{Color, Element, Font, Theme};
+use crate::anki::{Anki, Knowledge};
+use crate::cedict::Cedict;
+use crate::{make_enum, segment, Message};
+
+make_enum!(MarkMode, [Off, Anki, Hsk]);
+
+impl MarkMode {
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|m| m == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mark {
+    Known,
+    Learning,
+    Unknown,
+    Hsk(u32),
+}
+
+/// HSK levels are shown in pairs, 7-9 together
+const HSK_GROUPS: [&str; 4] = ["HSK 1-2", "HSK 3-4", "HSK 5-6", "HSK 7-9"];
+
+impl Mark {
+    /// Position in the legend
+    fn group(&self) -> usize {
+        match self {
+            Mark::Known => 0,
+            Mark::Learning => 1,
+            Mark::Unknown => 2,
+            Mark::Hsk(l) => (((*l).max(1) - 1) / 2).min(3) as usize,
+        }
+    }
+
+    fn color(&self, theme: &Theme) -> Option<Color> {
+        let p = theme.extended_palette();
+        match (self, self.group()) {
+            (Mark::Known, _) | (Mark::Hsk(_), 0) => Some(p.success.base.color),
+            (Mark::Learning, _) | (Mark::Hsk(_), 1) => Some(p.primary.base.color),
+            (Mark::Unknown, _) | (Mark::Hsk(_), 3) => Some(p.danger.base.color),
+            (Mark::Hsk(_), _) => Some(p.warning.base.color),
+        }
+    }
+}
+
+/// Words of the text with their marks, byte ranges per line
+#[derive(Clone, Debug, Default)]
+pub struct Marks {
+    version: u64,
+    pub mode: MarkMode,
+    lines: Arc<Vec<Vec<(Range<usize>, Mark)>>>,
+    /// Words in each legend group, and all dictionary words of the text
+    counts: [usize; 4],
+    total: usize,
+}
+
+/// Only the version is compared, the editor re-highlights when it changes
+impl PartialEq for Marks {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version
+    }
+}
+
+/// The best state of the word as written or in its other script
+fn knowledge(dict: &Cedict, anki: &Anki, word: &str, known_interval: i64) -> Knowledge {
+    let lookup = dict.lookup(word);
+    let forms = lookup.iter().flat_map(|m| [m.0.sim(), m.0.tra()]);
+    std::iter::once(word).chain(forms)
+        .map(|w| anki.knowledge(w, known_interval))
+        .min_by_key(|k| Mark::from(*k).group())
+        .unwrap_or(Knowledge::Unknown)
+}
+
+impl From<Knowledge> for Mark {
+    fn from(k: Knowledge) -> Self {
+        match k {
+            Knowledge::Known => Mark::Known,
+            Knowledge::Learning => Mark::Learning,
+            Knowledge::Unknown => Mark::Unknown,
+        }
+    }
+}
+
+impl Marks {
+    pub fn new(dict: &Cedict, anki: &Anki, text: &str, mode: MarkMode, known_interval: i64, version: u64) -> Self {
+        let mut counts = [0; 4];
+        let mut total = 0;
+        let lines = match mode {
+            MarkMode::Off => vec![],
+            _ => text.lines()
+                .map(|l| {
+                    let idx: Vec<usize> = l.char_indices().map(|(i, _)| i).chain([l.len()]).collect();
+                    segment::segment(dict, l).into_iter()
+                        .filter(|s| s.known)
+                        .filter_map(|s| {
+                            total += 1;
+                            let mark = match mode {
+                                MarkMode::Hsk => Mark::Hsk(dict.lookup(&s.word).iter().filter_map(|m| m.0.hsk()).min()?),
+                                _ => knowledge(dict, anki, &s.word, known_interval).into(),
+                            };
+                            counts[mark.group()] += 1;
+                            Some((idx[s.start]..idx[s.end], mark))
+                        })
+                        .collect()
+                })
+                .collect(),
+        };
+        Self { version, mode, lines: Arc::new(lines), counts, total }
+    }
+
+    pub fn empty(mode: MarkMode) -> Self {
+        Self { mode, ..Default::default() }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Colours used and the share of words in each of them
+    pub fn legend<'a>(&self, size: f32) -> Element<'a, Message> {
+        let groups: Vec<(Mark, &str)> = match self.mode {
+            MarkMode::Off => vec![],
+            MarkMode::Anki => vec![(Mark::Known, "Known"), (Mark::Learning, "Learning"), (Mark::Unknown, "Not in Anki")],
+            MarkMode::Hsk => HSK_GROUPS.iter().enumerate().map(|(i, g)| (Mark::Hsk(i as u32 * 2 + 1), *g)).collect(),
+        };
+        groups.into_iter().fold(Row::new().spacing(12), |r, (m, label)| {
+            let share = self.counts[m.group()] as f32 * 100.0 / self.total.max(1) as f32;
+            r.push(text(format!("■ {} {:.0}%", label, share))
+                .size(size)
+                .style(move |t: &Theme| text::Style { color: m.color(t) }))
+        }).into()
+    }
+}
+
+pub struct Marker {
+    marks: Marks,
+    line: usize,
+}
+
+impl Highlighter for Marker {
+    type Settings = Marks;
+    type Highlight = Mark;
+    type Iterator<'a> = std::vec::IntoIter<(Range<usize>, Mark)>;
+
+    fn new(settings: &Self::Settings) -> Self {
+        Self { marks: settings.clone(), line: 0 }
+    }
+
+    fn update(&mut self, new_settings: &Self::Settings) {
+        self.marks = new_settings.clone();
+        self.line = 0;
+    }
+
+    fn change_line(&mut self, line: usize) {
+        self.line = self.line.min(line);
+    }
+
+    /// Marks are refreshed after a pause in typing, until then edited lines
+    /// keep only the ranges that still fit them
+    fn highlight_line(&mut self, line: &str) -> Self::Iterator<'_> {
+        let mut res = self.marks.lines.get(self.line).cloned().unwrap_or_default();
+        res.retain(|(r, _)| r.end <= line.len() && line.is_char_boundary(r.start) && line.is_char_boundary(r.end));
+        self.line += 1;
+        res.into_iter()
+    }
+
+    fn current_line(&self) -> usize {
+        self.line
+    }
+}
+
+pub fn format(m: &Mark, theme: &Theme) -> highlighter::Format<Font> {
+    highlighter::Format { color: m.color(theme), font: None }
+}
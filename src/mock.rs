@@ -0,0 +1,75 @@
+//! Local stand-in for the HTTP services, used by tests
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// What the server got
+#[derive(Default)]
+pub struct Request {
+    pub path: String,
+    pub body: String,
+}
+
+impl Request {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+/// Answers one request with `status` and a body sent in `chunks`, each flushed separately.
+/// Returns the url of the server and the request it got.
+pub async fn serve(status: u16, chunks: Vec<Vec<u8>>) -> (String, JoinHandle<Request>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = tokio::spawn(async move {
+        let (mut s, _) = listener.accept().await.unwrap();
+        let req = read_request(&mut s).await;
+        // The client may stop reading before the end
+        let _ = respond(&mut s, status, chunks).await;
+        req
+    });
+    (url, handle)
+}
+
+async fn respond(s: &mut TcpStream, status: u16, chunks: Vec<Vec<u8>>) -> std::io::Result<()> {
+    let head = format!("HTTP/1.1 {} X\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n", status);
+    s.write_all(head.as_bytes()).await?;
+    for c in chunks {
+        s.write_all(format!("{:x}\r\n", c.len()).as_bytes()).await?;
+        s.write_all(&c).await?;
+        s.write_all(b"\r\n").await?;
+        s.flush().await?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    s.write_all(b"0\r\n\r\n").await
+}
+
+/// Splits `s` into pieces of `n` bytes, cutting through characters and lines
+pub fn pieces(s: &str, n: usize) -> Vec<Vec<u8>> {
+    s.as_bytes().chunks(n).map(|c| c.to_vec()).collect()
+}
+
+async fn read_request(s: &mut TcpStream) -> Request {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = s.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+        let Some(p) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            if n == 0 { return Request::default() }
+            continue;
+        };
+        let head = String::from_utf8_lossy(&buf[..p]).to_string();
+        let len = head.to_lowercase().lines()
+            .find_map(|l| l.strip_prefix("content-length:"))
+            .and_then(|l| l.trim().parse().ok())
+            .unwrap_or(0);
+        if buf.len() >= p + 4 + len || n == 0 {
+            return Request {
+                path: head.split(' ').nth(1).unwrap_or_default().to_string(),
+                body: String::from_utf8_lossy(&buf[p + 4..]).to_string(),
+            };
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use crate::make_enum;
+
+make_enum!(PinyinStyle, [Numbered, Marked, Zhuyin]);
+
+const MARKS: [(char, [char; 4]); 12] = [
+    ('a', ['ā', 'á', 'ǎ', 'à']),
+    ('e', ['ē', 'é', 'ě', 'è']),
+    ('i', ['ī', 'í', 'ǐ', 'ì']),
+    ('o', ['ō', 'ó', 'ǒ', 'ò']),
+    ('u', ['ū', 'ú', 'ǔ', 'ù']),
+    ('ü', ['ǖ', 'ǘ', 'ǚ', 'ǜ']),
+    ('A', ['Ā', 'Á', 'Ǎ', 'À']),
+    ('E', ['Ē', 'É', 'Ě', 'È']),
+    ('I', ['Ī', 'Í', 'Ǐ', 'Ì']),
+    ('O', ['Ō', 'Ó', 'Ǒ', 'Ò']),
+    ('U', ['Ū', 'Ú', 'Ǔ', 'Ù']),
+    ('Ü', ['Ǖ', 'Ǘ', 'Ǚ', 'Ǜ']),
+];
+
+/// Combining marks for syllables without a vowel (m2, ng4)
+const COMBINING: [char; 4] = ['\u{0304}', '\u{0301}', '\u{030C}', '\u{0300}'];
+
+const INITIALS: [(&str, &str); 21] = [
+    ("zh", "ㄓ"), ("ch", "ㄔ"), ("sh", "ㄕ"),
+    ("b", "ㄅ"), ("p", "ㄆ"), ("m", "ㄇ"), ("f", "ㄈ"),
+    ("d", "ㄉ"), ("t", "ㄊ"), ("n", "ㄋ"), ("l", "ㄌ"),
+    ("g", "ㄍ"), ("k", "ㄎ"), ("h", "ㄏ"),
+    ("j", "ㄐ"), ("q", "ㄑ"), ("x", "ㄒ"),
+    ("r", "ㄖ"), ("z", "ㄗ"), ("c", "ㄘ"), ("s", "ㄙ"),
+];
+
+const FINALS: [(&str, &str); 38] = [
+    ("a", "ㄚ"), ("o", "ㄛ"), ("e", "ㄜ"), ("ê", "ㄝ"),
+    ("ai", "ㄞ"), ("ei", "ㄟ"), ("ao", "ㄠ"), ("ou", "ㄡ"),
+    ("an", "ㄢ"), ("en", "ㄣ"), ("ang", "ㄤ"), ("eng", "ㄥ"),
+    ("er", "ㄦ"), ("ong", "ㄨㄥ"),
+    ("i", "ㄧ"), ("ia", "ㄧㄚ"), ("io", "ㄧㄛ"), ("ie", "ㄧㄝ"), ("iai", "ㄧㄞ"),
+    ("iao", "ㄧㄠ"), ("iu", "ㄧㄡ"), ("iou", "ㄧㄡ"), ("ian", "ㄧㄢ"), ("in", "ㄧㄣ"),
+    ("iang", "ㄧㄤ"), ("ing", "ㄧㄥ"), ("iong", "ㄩㄥ"),
+    ("u", "ㄨ"), ("ua", "ㄨㄚ"), ("uo", "ㄨㄛ"), ("uai", "ㄨㄞ"), ("ui", "ㄨㄟ"),
+    ("uei", "ㄨㄟ"), ("uan", "ㄨㄢ"), ("un", "ㄨㄣ"), ("uen", "ㄨㄣ"),
+    ("uang", "ㄨㄤ"), ("ueng", "ㄨㄥ"),
+];
+
+const FINALS_U: [(&str, &str); 4] = [("ü", "ㄩ"), ("üe", "ㄩㄝ"), ("üan", "ㄩㄢ"), ("ün", "ㄩㄣ")];
+
+/// Finals as spelled after an initial, `v` stands for ü
+const SPELLED_FINALS: [&str; 38] = [
+    "a", "o", "e", "ai", "ei", "ao", "ou", "an", "en", "ang", "eng", "ong", "er",
+    "i", "ia", "ie", "iao", "iu", "ian", "in", "iang", "ing", "iong",
+    "u", "ua", "uo", "uai", "ui", "uan", "un", "uang", "ue",
+    "v", "ve", "van", "vn", "r", "ng",
+];
+
+const ZHUYIN_TONES: [&str; 5] = ["", "ˊ", "ˇ", "ˋ", "˙"];
+
+/// Display style selected in the configuration
+pub fn style() -> PinyinStyle {
+    crate::CONFIG.get()
+        .and_then(|c| c.window.pinyin.clone())
+        .map(PinyinStyle::from)
+        .unwrap_or_default()
+}
+
+/// Render a space separated CEDICT reading (`ni3 hao3`)
+pub fn render(pin: &str, style: PinyinStyle) -> String {
+    match style {
+        PinyinStyle::Numbered => pin.to_string(),
+        PinyinStyle::Marked => pin.split(' ')
+            .map(|s| marked(s).unwrap_or_else(|| s.to_string()))
+            .collect::<Vec<String>>()
+            .join(" "),
+        PinyinStyle::Zhuyin => pin.split(' ')
+            .map(|s| zhuyin(s).unwrap_or_else(|| s.to_string()))
+            .collect::<Vec<String>>()
+            .join(" "),
+    }
+}
+
+/// Split `hao3` into (`hao`, 3), `u:` and `v` become `ü`
+fn split_tone(s: &str) -> Option<(String, usize)> {
+    let tone = s.chars().last()?.to_digit(10)? as usize;
+    let body = &s[..s.len() - 1];
+    if !(1..=5).contains(&tone) || body.is_empty() || !body.chars().all(|c| c.is_ascii_alphabetic() || c == ':' || c == 'ü' || c == 'Ü') {
+        return None;
+    }
+    let body = body.replace("u:", "ü").replace("U:", "Ü").replace('v', "ü").replace('V', "Ü");
+    Some((body, tone))
+}
+
+/// `hao3` -> `hǎo`
+pub fn marked(s: &str) -> Option<String> {
+    let (body, tone) = split_tone(s)?;
+    if tone == 5 {
+        return Some(body);
+    }
+    let chars: Vec<char> = body.chars().collect();
+    let lower: Vec<char> = body.to_lowercase().chars().collect();
+    let is_vowel = |c: &char| "aeiouü".contains(*c);
+    let pos = lower.iter().position(|&c| c == 'a' || c == 'e')
+        .or_else(|| lower.windows(2).position(|w| w == ['o', 'u']))
+        .or_else(|| lower.iter().rposition(is_vowel));
+
+    let mut res = String::new();
+    match pos {
+        Some(p) => {
+            for (i, c) in chars.iter().enumerate() {
+                match MARKS.iter().find(|(v, _)| *v == *c) {
+                    Some((_, m)) if i == p => res.push(m[tone - 1]),
+                    _ => res.push(*c),
+                }
+            }
+        }
+        None => {
+            res.push(chars[0]);
+            res.push(COMBINING[tone - 1]);
+            res.extend(&chars[1..]);
+        }
+    }
+    Some(res)
+}
+
+/// `hao3` -> `ㄏㄠˇ`
+pub fn zhuyin(s: &str) -> Option<String> {
+    let (body, tone) = split_tone(s)?;
+    let body = body.to_lowercase();
+    let tone = ZHUYIN_TONES[tone - 1];
+
+    if body == "r" {
+        return Some("ㄦ".to_string());
+    }
+
+    // y and w are only spelling, zhuyin writes the medial
+    let body = if let Some(r) = body.strip_prefix("yu") {
+        format!("ü{}", r)
+    } else if let Some(r) = body.strip_prefix("yi") {
+        format!("i{}", r)
+    } else if let Some(r) = body.strip_prefix('y') {
+        format!("i{}", r)
+    } else if let Some(r) = body.strip_prefix("wu") {
+        format!("u{}", r)
+    } else if let Some(r) = body.strip_prefix('w') {
+        format!("u{}", r)
+    } else {
+        body
+    };
+
+    let (ini, fin) = match INITIALS.iter().find(|(p, _)| body.starts_with(p)) {
+        Some((p, z)) => (*z, &body[p.len()..]),
+        None => ("", body.as_str()),
+    };
+
+    // zhi, chi, shi, ri, zi, ci, si have no final in zhuyin
+    if fin == "i" && matches!(ini, "ㄓ" | "ㄔ" | "ㄕ" | "ㄖ" | "ㄗ" | "ㄘ" | "ㄙ") {
+        return Some(format_zhuyin(ini, "", tone));
+    }
+
+    // ju, qu, xu are written with u but read ü
+    let fin = if matches!(ini, "ㄐ" | "ㄑ" | "ㄒ") && fin.starts_with('u') {
+        format!("ü{}", &fin[1..])
+    } else {
+        fin.to_string()
+    };
+
+    FINALS.iter().chain(FINALS_U.iter())
+        .find(|(p, _)| *p == fin)
+        .map(|(_, z)| format_zhuyin(ini, z, tone))
+}
+
+fn format_zhuyin(ini: &str, fin: &str, tone: &str) -> String {
+    if tone == "˙" {
+        format!("˙{}{}", ini, fin)
+    } else {
+        format!("{}{}{}", ini, fin, tone)
+    }
+}
+
+/// Reading reduced for searching: lowercase, no separators, `u:` and `ü` written as `v`
+pub fn compact(s: &str) -> String {
+    s.to_lowercase()
+        .replace("u:", "v")
+        .replace('ü', "v")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect()
+}
+
+fn is_syllable(s: &str) -> bool {
+    let fin = match ["zh", "ch", "sh", "b", "p", "m", "f", "d", "t", "n", "l", "g", "k", "h",
+        "j", "q", "x", "r", "z", "c", "s", "y", "w"].iter().find(|p| s.starts_with(*p)) {
+        Some(p) => &s[p.len()..],
+        None => s,
+    };
+    SPELLED_FINALS.contains(&fin) || (fin.is_empty() && matches!(s, "m" | "n" | "ng" | "hm" | "hng"))
+}
+
+/// Whether the input can be read as a sequence of pinyin syllables with optional tones
+pub fn is_pinyin(s: &str) -> bool {
+    let s: String = compact(s).chars().filter(|c| !c.is_ascii_digit()).collect();
+    let n = s.len();
+    if n == 0 || !s.is_ascii() {
+        return false;
+    }
+    let mut ok = vec![false; n + 1];
+    ok[0] = true;
+    for i in 0..n {
+        if ok[i] {
+            for j in (i + 1)..=(i + 6).min(n) {
+                if is_syllable(&s[i..j]) {
+                    ok[j] = true;
+                }
+            }
+        }
+    }
+    ok[n]
+}
+
+/// Match a compact query against the start of a compact reading, tones left out in the query
+/// match any tone. Returns whether the whole reading was matched.
+pub fn match_compact(q: &str, e: &str) -> Option<bool> {
+    let (q, e) = (q.as_bytes(), e.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while i < q.len() {
+        if j == e.len() {
+            return None;
+        }
+        if q[i] == e[j] {
+            i += 1;
+            j += 1;
+        } else if e[j].is_ascii_digit() {
+            j += 1;
+        } else {
+            return None;
+        }
+    }
+    if j < e.len() && e[j].is_ascii_digit() {
+        j += 1;
+    }
+    Some(j == e.len())
+}
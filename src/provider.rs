@@ -0,0 +1,166 @@
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::debug;
+use crate::chat::Turn;
+use crate::config::AiChatConfiguration;
+use crate::make_enum;
+use crate::sse::EventStream;
+
+make_enum!(Api, [OpenAI, Ollama]);
+
+/// A part of a streamed answer, reasoning models send their thoughts first
+#[derive(Debug, Default)]
+pub struct Part {
+    pub answer: String,
+    pub reasoning: String,
+}
+
+/// A chat API: how to ask for a streamed answer and how to read its parts
+pub trait Provider: Send + Sync {
+    fn request(&self, client: &reqwest::Client, messages: &[Turn]) -> reqwest::RequestBuilder;
+
+    fn events(&self, resp: reqwest::Response) -> EventStream {
+        EventStream::new(resp)
+    }
+
+    /// One event of the answer, None when the answer is complete
+    fn parse(&self, data: &str) -> Result<Option<Part>>;
+}
+
+pub fn provider(conf: &AiChatConfiguration) -> Box<dyn Provider> {
+    match conf.provider.clone().map(Api::from).unwrap_or_default() {
+        Api::OpenAI => Box::new(OpenAI(conf.clone())),
+        Api::Ollama => Box::new(Ollama(conf.clone())),
+    }
+}
+
+/// HTTP client with the timeouts and extra headers of the chat
+pub fn client(conf: &AiChatConfiguration) -> Result<reqwest::Client> {
+    let mut headers = HeaderMap::new();
+    for (k, v) in conf.headers.iter().flatten() {
+        headers.insert(HeaderName::from_bytes(k.as_bytes())?, HeaderValue::from_str(v)?);
+    }
+    let mut builder = reqwest::Client::builder().default_headers(headers);
+    if let Some(t) = conf.timeout {
+        let t = Duration::from_secs(t);
+        builder = builder.connect_timeout(t).read_timeout(t);
+    }
+    Ok(builder.build()?)
+}
+
+fn messages(messages: &[Turn]) -> Vec<Value> {
+    messages.iter()
+        .map(|t| json!({"role": t.role.as_str().to_lowercase(), "content": t.content}))
+        .collect()
+}
+
+fn post(client: &reqwest::Client, conf: &AiChatConfiguration, path: &str, body: Value) -> reqwest::RequestBuilder {
+    let req = client.post(format!("{}/{}", conf.url.trim_end_matches('/'), path)).json(&body);
+    if conf.key.is_empty() { req } else { req.bearer_auth(&conf.key) }
+}
+
+/// Any OpenAI compatible endpoint, `url` without `/chat/completions`
+pub struct OpenAI(AiChatConfiguration);
+
+#[derive(Deserialize)]
+struct Chunk {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    error: Option<ApiError>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    delta: Delta,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+    reasoning_content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+impl Provider for OpenAI {
+    fn request(&self, client: &reqwest::Client, m: &[Turn]) -> reqwest::RequestBuilder {
+        let mut body = json!({"model": self.0.model, "messages": messages(m), "stream": true});
+        if let Some(t) = self.0.temperature {
+            body["temperature"] = json!(t);
+        }
+        if let Some(n) = self.0.max_tokens {
+            body["max_tokens"] = json!(n);
+        }
+        post(client, &self.0, "chat/completions", body)
+    }
+
+    fn parse(&self, data: &str) -> Result<Option<Part>> {
+        if data == "[DONE]" {
+            return Ok(None);
+        }
+        match serde_json::from_str::<Chunk>(data) {
+            Ok(Chunk { error: Some(e), .. }) => Err(anyhow!(e.message)),
+            Ok(c) => Ok(Some(c.choices.into_iter().fold(Part::default(), |mut p, c| {
+                p.answer.extend(c.delta.content);
+                p.reasoning.extend(c.delta.reasoning_content);
+                p
+            }))),
+            Err(e) => {
+                debug!("Skipping chunk {}: {}", data, e);
+                Ok(Some(Part::default()))
+            }
+        }
+    }
+}
+
+/// Native Ollama API, `url` of the server, e.g. http://localhost:11434
+pub struct Ollama(AiChatConfiguration);
+
+#[derive(Deserialize)]
+struct OllamaChunk {
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OllamaMessage {
+    content: String,
+    thinking: Option<String>,
+}
+
+impl Provider for Ollama {
+    fn request(&self, client: &reqwest::Client, m: &[Turn]) -> reqwest::RequestBuilder {
+        let mut options = json!({});
+        if let Some(t) = self.0.temperature {
+            options["temperature"] = json!(t);
+        }
+        if let Some(n) = self.0.max_tokens {
+            options["num_predict"] = json!(n);
+        }
+        let body = json!({"model": self.0.model, "messages": messages(m), "stream": true, "options": options});
+        post(client, &self.0, "api/chat", body)
+    }
+
+    fn events(&self, resp: reqwest::Response) -> EventStream {
+        EventStream::json_lines(resp)
+    }
+
+    fn parse(&self, data: &str) -> Result<Option<Part>> {
+        let c: OllamaChunk = serde_json::from_str(data)?;
+        if let Some(e) = c.error {
+            return Err(anyhow!(e));
+        }
+        if c.done {
+            return Ok(None);
+        }
+        Ok(Some(c.message.map(|m| Part { answer: m.content, reasoning: m.thinking.unwrap_or_default() }).unwrap_or_default()))
+    }
+}
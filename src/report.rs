@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use crate::anki::Anki;
+use crate::cedict::Cedict;
+use crate::{pinyin, segment};
+
+/// A dictionary word of the text
+#[derive(Clone, Debug)]
+pub struct WordStat {
+    pub word: String,
+    pub traditional: String,
+    pub reading: String,
+    pub meaning: String,
+    pub hsk: Option<u32>,
+    pub count: usize,
+    pub in_anki: bool,
+    /// Line and character column of the first occurrence
+    pub first: (usize, usize),
+}
+
+/// How hard a text is for the reader: HSK levels of its words and how many of them are in Anki
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub tokens: usize,
+    pub chars: usize,
+    pub unique_chars: usize,
+    /// Unique words by HSK level, None for words outside HSK
+    pub hsk: BTreeMap<Option<u32>, usize>,
+    /// Sorted by count, most frequent first
+    pub words: Vec<WordStat>,
+}
+
+impl Report {
+    pub fn new(dict: &Cedict, anki: &Anki, text: &str) -> Self {
+        let mut res = Self::default();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let mut chars: HashSet<char> = HashSet::new();
+
+        for (ln, segments) in segment::segment_text(dict, text).iter().enumerate() {
+            for s in segments.iter().filter(|s| s.word.chars().any(segment::is_han)) {
+                chars.extend(s.word.chars().filter(|c| segment::is_han(*c)));
+                res.chars += s.word.chars().filter(|c| segment::is_han(*c)).count();
+                if !s.known {
+                    continue;
+                }
+                res.tokens += 1;
+                if let Some(&i) = seen.get(&s.word) {
+                    res.words[i].count += 1;
+                    continue;
+                }
+                let found = dict.lookup(&s.word);
+                let Some(e) = found.iter().map(|m| m.0).min_by_key(|e| e.hsk().unwrap_or(u32::MAX)) else { continue };
+                seen.insert(s.word.clone(), res.words.len());
+                res.words.push(WordStat {
+                    word: s.word.clone(),
+                    traditional: e.tra().to_string(),
+                    reading: pinyin::render(e.pin(), pinyin::style()),
+                    meaning: e.mea().split('/').filter(|m| !m.is_empty()).collect::<Vec<&str>>().join("; "),
+                    hsk: e.hsk(),
+                    count: 1,
+                    in_anki: found.iter().any(|m| anki.contains(m.0.sim()) || anki.contains(m.0.tra())),
+                    first: (ln, s.start),
+                });
+            }
+        }
+
+        res.unique_chars = chars.len();
+        for w in &res.words {
+            *res.hsk.entry(w.hsk).or_default() += 1;
+        }
+        res.words.sort_by(|a, b| b.count.cmp(&a.count).then(a.first.cmp(&b.first)));
+        res
+    }
+
+    pub fn unknown(&self) -> impl Iterator<Item = &WordStat> {
+        self.words.iter().filter(|w| !w.in_anki)
+    }
+
+    /// Share of unique words and of all words of the text found in Anki
+    pub fn coverage(&self) -> (f32, f32) {
+        let unique = self.words.iter().filter(|w| w.in_anki).count();
+        let tokens: usize = self.words.iter().filter(|w| w.in_anki).map(|w| w.count).sum();
+        (
+            unique as f32 * 100.0 / self.words.len().max(1) as f32,
+            tokens as f32 * 100.0 / self.tokens.max(1) as f32,
+        )
+    }
+
+    /// Unknown words as CSV
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from("word,pinyin,hsk,count,meaning\n");
+        for w in self.unknown() {
+            let hsk = w.hsk.map(|h| h.to_string()).unwrap_or_default();
+            let count = w.count.to_string();
+            let row = [w.word.as_str(), w.reading.as_str(), hsk.as_str(), count.as_str(), w.meaning.as_str()]
+                .map(csv_field)
+                .join(",");
+            res.push_str(&row);
+            res.push('\n');
+        }
+        res
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Words: {} ({} unique)", self.tokens, self.words.len())?;
+        writeln!(f, "Characters: {} ({} unique)", self.chars, self.unique_chars)?;
+        let levels = self.hsk.iter()
+            .filter(|(l, _)| l.is_some())
+            .chain(self.hsk.iter().filter(|(l, _)| l.is_none()))
+            .map(|(l, n)| match l {
+                Some(l) => format!("HSK {}: {}", l, n),
+                None => format!("Other: {}", n),
+            })
+            .collect::<Vec<String>>();
+        writeln!(f, "{}", levels.join("  "))?;
+        let (unique, tokens) = self.coverage();
+        writeln!(f, "In Anki: {:.0}% of words, {:.0}% of the text", unique, tokens)?;
+        writeln!(f, "\nUnknown words:")?;
+        for w in self.unknown() {
+            let hsk = w.hsk.map(|h| format!(" HSK {}", h)).unwrap_or_default();
+            writeln!(f, "{:>4}  {} [{}]{} {}", w.count, w.word, w.reading, hsk, w.meaning)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use crate::cedict::{Cedict, Entry};
+use crate::helper;
+
+/// A word found in a line, `start` and `end` are character offsets
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    pub word: String,
+    pub known: bool,
+}
+
+impl Segment {
+    pub fn contains(&self, col: usize) -> bool {
+        self.start <= col && col < self.end
+    }
+}
+
+pub fn is_han(c: char) -> bool {
+    matches!(c,
+        '\u{3007}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2EBEF}')
+}
+
+/// Longer words win over several short ones, HSK words over obscure ones
+fn weight(e: &Entry) -> u32 {
+    let l = e.len() as u32;
+    l * l * 10 + if e.hsk().is_some() { 5 } else { 0 }
+}
+
+/// Split a line into words, picking the path with the best total weight
+pub fn segment(dict: &Cedict, line: &str) -> Vec<Segment> {
+    let chars: Vec<char> = line.chars().collect();
+    let n = chars.len();
+    // best[i] = (score, start of the last word, word is in the dictionary)
+    let mut best: Vec<Option<(u32, usize, bool)>> = vec![None; n + 1];
+    best[0] = Some((0, 0, false));
+
+    for i in 0..n {
+        let Some((score, _, _)) = best[i] else { continue };
+        let mut relax = |j: usize, s: u32, known: bool| {
+            if best[j].is_none_or(|b| s > b.0) {
+                best[j] = Some((s, i, known));
+            }
+        };
+
+        if is_han(chars[i]) {
+            relax(i + 1, score, false);
+        } else {
+            let j = chars[i..].iter().position(|c| is_han(*c)).map_or(n, |p| i + p);
+            relax(j, score, false);
+        }
+        for e in dict.prefixes(&chars[i..]) {
+            relax(i + e.len(), score + weight(e), true);
+        }
+    }
+
+    let mut res = vec![];
+    let mut end = n;
+    while end > 0 {
+        let (_, start, known) = best[end].unwrap();
+        res.push(Segment {
+            start,
+            end,
+            word: chars[start..end].iter().collect(),
+            known,
+        });
+        end = start;
+    }
+    res.reverse();
+    res
+}
+
+/// Segment every line of a text
+pub fn segment_text(dict: &Cedict, text: &str) -> Vec<Vec<Segment>> {
+    text.lines().map(|l| segment(dict, l)).collect()
+}
+
+/// The word under a cursor placed at `col`, falls back to the one before it
+pub fn word_at(segments: &[Segment], col: usize) -> Option<&Segment> {
+    segments.iter().find(|s| s.contains(col))
+        .or_else(|| segments.iter().find(|s| col > 0 && s.contains(col - 1)))
+}
+
+/// The word under the editor cursor at the byte `col` of `line`
+pub fn word_under_cursor(dict: &Cedict, line: &str, col: usize) -> Option<Segment> {
+    let segments = segment(dict, line);
+    word_at(&segments, helper::char_col(line, col)).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn click_on_multi_byte_line() {
+        let dict = Cedict::from_rows(&[
+            ("我们", "我們", "wo3 men5", "/we/us/"),
+            ("学习", "學習", "xue2 xi2", "/to learn/"),
+            ("中文", "中文", "Zhong1 wen2", "/Chinese language/"),
+        ]);
+        let word = |col| word_under_cursor(&dict, "我们学习中文", col).map(|s| s.word);
+        assert_eq!(word(0).as_deref(), Some("我们"));
+        // 学 starts at byte 6, 习 at byte 9, 中 at byte 12
+        assert_eq!(word(6).as_deref(), Some("学习"));
+        assert_eq!(word(9).as_deref(), Some("学习"));
+        assert_eq!(word(12).as_deref(), Some("中文"));
+        // After the last character
+        assert_eq!(word(18).as_deref(), Some("中文"));
+    }
+}
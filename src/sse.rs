@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use tokio_stream::{Stream, StreamExt};
+
+type Body = Pin<Box<dyn Stream<Item = reqwest::Result<Vec<u8>>> + Send>>;
+
+/// Server-sent events of a streamed response, only the data of each event is kept.
+/// Responses with a JSON object per line are read the same way, one line per event.
+pub struct EventStream {
+    body: Body,
+    json_lines: bool,
+    /// Bytes after the last complete line, a chunk can end inside a character
+    buf: Vec<u8>,
+    /// Data lines of the event being read
+    data: Option<String>,
+    events: VecDeque<String>,
+}
+
+impl EventStream {
+    pub fn new(resp: reqwest::Response) -> Self {
+        let body = resp.bytes_stream().map(|c| c.map(Vec::from));
+        Self { body: Box::pin(body), json_lines: false, buf: vec![], data: None, events: VecDeque::new() }
+    }
+
+    pub fn json_lines(resp: reqwest::Response) -> Self {
+        Self { json_lines: true, ..Self::new(resp) }
+    }
+
+    /// Waits for the next event, None when the response is complete
+    pub async fn next(&mut self) -> reqwest::Result<Option<String>> {
+        loop {
+            if let Some(e) = self.events.pop_front() {
+                return Ok(Some(e));
+            }
+            match self.body.next().await {
+                Some(chunk) => self.push(&chunk?),
+                None => {
+                    // The last event may miss its blank line
+                    self.push(b"\n\n");
+                    return Ok(self.events.pop_front());
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        while let Some(p) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=p).collect();
+            let line = String::from_utf8_lossy(&line);
+            self.line(line.trim_end_matches(['\n', '\r']));
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        if self.json_lines {
+            if !line.trim().is_empty() {
+                self.events.push_back(line.to_string());
+            }
+        } else if line.is_empty() {
+            if let Some(d) = self.data.take() {
+                self.events.push_back(d);
+            }
+        } else if let Some(v) = line.strip_prefix("data:") {
+            let v = v.strip_prefix(' ').unwrap_or(v);
+            match &mut self.data {
+                Some(d) => {
+                    d.push('\n');
+                    d.push_str(v);
+                }
+                None => self.data = Some(v.to_string()),
+            }
+        }
+        // Comments and the event, id and retry fields are not used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    /// Events of `body` served in pieces of `n` bytes
+    async fn events(body: &str, n: usize, json_lines: bool) -> Vec<String> {
+        let (url, _) = mock::serve(200, mock::pieces(body, n)).await;
+        let resp = reqwest::get(url).await.unwrap();
+        let mut s = if json_lines { EventStream::json_lines(resp) } else { EventStream::new(resp) };
+        let mut res = vec![];
+        while let Some(e) = s.next().await.unwrap() {
+            res.push(e);
+        }
+        res
+    }
+
+    #[tokio::test]
+    async fn split_chunks() {
+        // Small pieces cut the prefixes, the CRLFs and the 3 byte characters
+        let body = "data: 你好\n\ndata:世界！\r\n\r\n";
+        for n in [1, 2, 4, 5] {
+            assert_eq!(events(body, n, false).await, ["你好", "世界！"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn multi_line_data() {
+        let body = ": keep-alive\n\nevent: message\ndata: first\ndata:  second\nid: 1\n\n";
+        assert_eq!(events(body, 100, false).await, ["first\n second"]);
+    }
+
+    #[tokio::test]
+    async fn done_is_an_event() {
+        assert_eq!(events("data: a\n\ndata: [DONE]\n\n", 3, false).await, ["a", "[DONE]"]);
+    }
+
+    #[tokio::test]
+    async fn last_event_without_blank_line() {
+        assert_eq!(events("data: a\n\ndata: b", 3, false).await, ["a", "b"]);
+        assert_eq!(events("data: a\n\ndata: b\n", 3, false).await, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn json_lines() {
+        let body = "{\"a\":\"你\"}\n\n{\"b\":1}";
+        assert_eq!(events(body, 4, true).await, ["{\"a\":\"你\"}", "{\"b\":1}"]);
+    }
+}